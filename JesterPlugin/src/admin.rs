@@ -0,0 +1,233 @@
+use crate::classify::FileKind;
+use crate::config::Configuration;
+use crate::errors::ISUProcessorError;
+use crate::format::{ChunkLimits, OutputFormat};
+use crate::runs::{self, RunStats};
+use crate::{
+    create_tables, fetch_file, initial_event_process, initial_process, save_file, tracked_files,
+    ISUProcessor, OutputOptions, Pass,
+};
+use rusqlite::Connection;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// TrackedFile is the state kept for a DAS file the plugin has read
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedFile {
+    pub path: PathBuf,
+    pub last_position_read: i64,
+    pub last_index: i64,
+    pub run_start: String,
+    pub updated_at: Option<String>, // files last read before update times were stored have none
+    pub finished: bool,
+}
+
+impl TrackedFile {
+    // unprocessed_bytes is how much of the file is past the last position read, None when the file
+    // is gone
+    pub fn unprocessed_bytes(&self) -> Option<u64> {
+        let len = fs::metadata(&self.path).ok()?.len();
+        Some(len.saturating_sub(self.last_position_read.try_into().unwrap_or(0)))
+    }
+}
+
+// StateAdmin inspects and changes the state database of the plugin. Jester should be stopped while
+// the state is changed, a running plugin would overwrite the state of a file it's tailing
+pub struct StateAdmin {
+    processor: ISUProcessor,
+}
+
+impl StateAdmin {
+    pub fn open(config: Configuration) -> Result<StateAdmin, ISUProcessorError> {
+        let processor = ISUProcessor::with_config(config)?;
+        create_tables(&*processor.state.lock()?)?;
+        Ok(StateAdmin { processor })
+    }
+
+    // files lists the tracked files, with a target only the file at that path or the files of the
+    // run directory at that path
+    pub fn files(&self, target: Option<&Path>) -> Result<Vec<TrackedFile>, ISUProcessorError> {
+        let db = self.processor.state.lock()?;
+        let mut stmt = db.prepare(
+            "SELECT path, last_position_read, last_index, time, updated_at, finished FROM isu ORDER BY path",
+        )?;
+        let files = stmt
+            .query_map([], |row| {
+                Ok(TrackedFile {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    last_position_read: row.get(1)?,
+                    last_index: row.get(2)?,
+                    run_start: row.get(3)?,
+                    updated_at: row.get(4)?,
+                    finished: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<TrackedFile>, rusqlite::Error>>()?;
+
+        Ok(match target {
+            None => files,
+            Some(target) => files
+                .into_iter()
+                .filter(|f| f.path == target || f.path.parent() == Some(target))
+                .collect(),
+        })
+    }
+
+    // rewind sets the target files back so the next pass reads them again from row index, 0 reads
+    // a file from the start as though it had never been seen. Outputs already sent for those rows
+    // are not recalled. Finished files are tailed again once rewound. The stats of their runs are
+    // counted again so rows read twice aren't in them twice, files of a closed run can't be rewound
+    // as its summary has been sent
+    pub fn rewind(&self, target: &Path, index: i64) -> Result<Vec<PathBuf>, ISUProcessorError> {
+        let files = self.targets(target)?;
+        if let Some(file) = files.iter().find(|f| f.last_index < index) {
+            return Err(ISUProcessorError::StateError(format!(
+                "{:?} has only been read to row {}",
+                file.path, file.last_index
+            )));
+        }
+
+        let db = self.processor.state.lock()?;
+        let tx = db.unchecked_transaction()?;
+        let mut directories = BTreeSet::new();
+        for file in &files {
+            let directory = file.path.parent().ok_or(ISUProcessorError::BlankPath)?;
+            if runs::is_closed(&tx, directory)? {
+                return Err(ISUProcessorError::StateError(format!(
+                    "the run of {:?} has been closed and its summary sent",
+                    file.path
+                )));
+            }
+            directories.insert(directory.to_path_buf());
+        }
+
+        for file in &files {
+            if index == 0 {
+                forget(&file.path, &tx)?;
+            } else {
+                self.rewind_file(&file.path, index, &tx)?;
+            }
+            log::info!("rewound {:?} to row {index}", file.path);
+        }
+        for directory in directories {
+            let stats = self.run_stats(&directory, &tx)?;
+            runs::replace(&tx, &directory, &stats)?;
+        }
+        tx.commit()?;
+
+        Ok(files.into_iter().map(|f| f.path).collect())
+    }
+
+    // finish marks the target files as finished, they're skipped instead of tailed from then on
+    pub fn finish(&self, target: &Path) -> Result<Vec<PathBuf>, ISUProcessorError> {
+        let files = self.targets(target)?;
+
+        let db = self.processor.state.lock()?;
+        let tx = db.unchecked_transaction()?;
+        for file in &files {
+            tx.execute(
+                "UPDATE isu SET finished = 1 WHERE path = ?1",
+                [file.path.to_string_lossy().as_ref()],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(files.into_iter().map(|f| f.path).collect())
+    }
+
+    fn targets(&self, target: &Path) -> Result<Vec<TrackedFile>, ISUProcessorError> {
+        let files = self.files(Some(target))?;
+        if files.is_empty() {
+            return Err(ISUProcessorError::StateError(format!(
+                "no tracked files at {target:?}"
+            )));
+        }
+
+        Ok(files)
+    }
+
+    // rewind_file reads the file again up to the row at index, the same way the plugin does, so the
+    // stored position, segment header and aggregation window are those the row was read with. Only
+    // the state of that pass is kept
+    fn rewind_file(
+        &self,
+        path: &Path,
+        index: i64,
+        db: &Connection,
+    ) -> Result<(), ISUProcessorError> {
+        let source = path.to_str().ok_or(ISUProcessorError::BlankPath)?;
+        let stored = fetch_file(source, db)?.ok_or(ISUProcessorError::BlankPath)?;
+
+        let mut file = self.read_to(path, index)?.file.ok_or_else(|| {
+            ISUProcessorError::StateError(format!("the header of {path:?} isn't complete"))
+        })?;
+        if i64::from(file.last_index) < index {
+            return Err(ISUProcessorError::StateError(format!(
+                "{path:?} only has {} rows now, it has changed since it was read",
+                file.last_index
+            )));
+        }
+
+        // outputs keep being numbered after the ones already sent
+        file.sequence = stored.sequence;
+        save_file(file, db)?;
+        db.execute("UPDATE isu SET finished = 0 WHERE path = ?1", [source])?;
+        Ok(())
+    }
+
+    // run_stats counts the rows the run's tracked files have been read up to, each once
+    fn run_stats(&self, directory: &Path, db: &Connection) -> Result<RunStats, ISUProcessorError> {
+        let mut stats = RunStats::default();
+        for path in tracked_files(directory, db)? {
+            let source = path.to_str().ok_or(ISUProcessorError::BlankPath)?;
+            let Some(file) = fetch_file(source, db)? else {
+                continue;
+            };
+            stats.merge(&self.read_to(&path, file.last_index.into())?.stats);
+        }
+
+        Ok(stats)
+    }
+
+    // read_to reads the file from the start up to the row at index, the same way the plugin does.
+    // The outputs of the pass are thrown away
+    fn read_to(&self, path: &Path, index: i64) -> Result<Pass, ISUProcessorError> {
+        let kind = self.processor.rules.classify(path)?;
+
+        let scratch = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&scratch)?;
+        let new_output = |sequence| scratch.join(format!("{sequence}.csv"));
+        let options = OutputOptions {
+            validator: &self.processor.validator,
+            channels: &self.processor.channels,
+            aggregation: self.processor.config.aggregation.get(&kind),
+            format: OutputFormat::Csv,
+            limits: ChunkLimits::default(),
+            stop_index: Some(index),
+            manifests: false,
+        };
+
+        let tz = self.processor.timezone;
+        let result = match kind {
+            FileKind::Events => initial_event_process(path.to_path_buf(), &new_output, &options, 0, tz),
+            _ => initial_process(path.to_path_buf(), &new_output, &options, 0, tz),
+        };
+        fs::remove_dir_all(&scratch)?;
+        result
+    }
+}
+
+// forget removes everything stored about a file
+fn forget(path: &Path, db: &Connection) -> Result<(), ISUProcessorError> {
+    let path = path.to_string_lossy();
+    for table in ["isu", "isu_lvm_header", "isu_identity", "isu_window"] {
+        db.execute(
+            format!("DELETE FROM {table} WHERE path = ?1").as_str(),
+            [path.as_ref()],
+        )?;
+    }
+
+    Ok(())
+}
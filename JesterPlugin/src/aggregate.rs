@@ -0,0 +1,219 @@
+use crate::channels::ChannelMap;
+use crate::format::{self, ColumnType, OutputFormat};
+use crate::graph::NON_CHANNEL_COLUMNS;
+use crate::timestamps::{self, DATETIME_COLUMN, TIMESTAMP_COLUMN};
+use chrono::{DateTime, Duration, Utc};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// the statistics written for each channel, in column order
+pub const STATISTICS: [&str; 5] = ["mean", "min", "max", "last", "count"];
+
+// Aggregation is the aggregation configured for a file kind. Windows are interval_seconds long and
+// start at the start of the run
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Aggregation {
+    pub interval_seconds: f64,
+    #[serde(default)]
+    pub raw: bool, // whether every sample is sent as well
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelStats {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub last: f64,
+}
+
+impl ChannelStats {
+    pub fn new(value: f64) -> ChannelStats {
+        ChannelStats {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+            last: value,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.last = value;
+    }
+
+    // merge adds statistics of later values
+    pub fn merge(&mut self, other: &ChannelStats) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.last = other.last;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+// Window holds the statistics of one interval, numbered from the start of the run. A channel
+// without a numeric value in the window has no statistics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub index: i64,
+    pub channels: Vec<Option<ChannelStats>>,
+}
+
+// aggregate_path is where the windows of an output are written, next to the output
+pub fn aggregate_path(output: &Path, aggregation: &Aggregation, format: OutputFormat) -> PathBuf {
+    format::sibling_path(
+        output,
+        format!("{}s", aggregation.interval_seconds).as_str(),
+        format.extension(),
+    )
+}
+
+// Aggregator collects rows into windows. A window is only written once a sample past it arrives,
+// the window still open at the end of a pass is stored with the file and carried on the next pass
+pub struct Aggregator {
+    interval: Duration,
+    run_start: DateTime<Utc>,
+    columns: Vec<usize>, // indexes of the channel columns in a row
+    window: Option<Window>,
+}
+
+impl Aggregator {
+    pub fn new(
+        aggregation: &Aggregation,
+        run_start: DateTime<Utc>,
+        window: Option<Window>,
+    ) -> Aggregator {
+        Aggregator {
+            interval: Duration::milliseconds((aggregation.interval_seconds * 1000.0) as i64),
+            run_start,
+            columns: vec![],
+            window,
+        }
+    }
+
+    // set_columns picks the channels out of the row headers, a stored window that was collected
+    // under other headers can't be continued and is dropped
+    pub fn set_columns(&mut self, headers: &[String]) {
+        self.columns = headers
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| !NON_CHANNEL_COLUMNS.contains(&h.as_str()))
+            .map(|(i, _)| i)
+            .collect();
+
+        if let Some(w) = &self.window {
+            if w.channels.len() != self.columns.len() {
+                log::warn!(
+                    "dropping aggregation window {}, the columns have changed",
+                    w.index
+                );
+                self.window = None;
+            }
+        }
+    }
+
+    // headers are the output names of the columns of a window row
+    pub fn headers(&self, headers: &[String], channels: &ChannelMap) -> Vec<String> {
+        let mut names = vec![
+            channels.name(DATETIME_COLUMN),
+            channels.name(TIMESTAMP_COLUMN),
+        ];
+        for column in &self.columns {
+            let name = headers
+                .get(*column)
+                .map(|h| channels.name(h))
+                .unwrap_or_default();
+            names.extend(STATISTICS.iter().map(|s| format!("{name}_{s}")));
+        }
+
+        names
+    }
+
+    // types are the types of the columns of a window row
+    pub fn types(&self) -> Vec<ColumnType> {
+        let mut types = vec![ColumnType::Timestamp, ColumnType::Timestamp];
+        for _ in &self.columns {
+            types.extend([
+                ColumnType::Float,
+                ColumnType::Float,
+                ColumnType::Float,
+                ColumnType::Float,
+                ColumnType::Integer,
+            ]);
+        }
+
+        types
+    }
+
+    // add adds a row sampled at time to its window, returning the row of the window it closed
+    pub fn add(&mut self, record: &StringRecord, time: DateTime<Utc>) -> Option<StringRecord> {
+        let index = (time - self.run_start)
+            .num_milliseconds()
+            .div_euclid(self.interval.num_milliseconds().max(1));
+
+        let closed = match &self.window {
+            Some(w) if w.index == index => None,
+            _ => self.close(),
+        };
+
+        let window = self.window.get_or_insert_with(|| Window {
+            index,
+            channels: vec![None; self.columns.len()],
+        });
+        for (stats, column) in window.channels.iter_mut().zip(&self.columns) {
+            let value = match record.get(*column).map(|v| v.trim().parse::<f64>()) {
+                Some(Ok(v)) if v.is_finite() => v,
+                _ => continue,
+            };
+
+            match stats {
+                None => *stats = Some(ChannelStats::new(value)),
+                Some(s) => s.add(value),
+            }
+        }
+
+        closed
+    }
+
+    // close ends the open window, returning its row
+    pub fn close(&mut self) -> Option<StringRecord> {
+        let window = self.window.take()?;
+
+        let mut record = StringRecord::new();
+        record.push_field(timestamps::format_time(Some(self.run_start)).as_str());
+        let start = self.run_start
+            + Duration::milliseconds(self.interval.num_milliseconds() * window.index);
+        record.push_field(timestamps::format_time(Some(start)).as_str());
+        for stats in &window.channels {
+            match stats {
+                None => {
+                    record.extend(["", "", "", ""]);
+                    record.push_field("0");
+                }
+                Some(s) => {
+                    record.push_field(s.mean().to_string().as_str());
+                    record.push_field(s.min.to_string().as_str());
+                    record.push_field(s.max.to_string().as_str());
+                    record.push_field(s.last.to_string().as_str());
+                    record.push_field(s.count.to_string().as_str());
+                }
+            }
+        }
+
+        Some(record)
+    }
+
+    pub fn window(&self) -> Option<Window> {
+        self.window.clone()
+    }
+}
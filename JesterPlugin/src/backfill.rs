@@ -0,0 +1,173 @@
+use crate::classify::{FileKind, Rules};
+use crate::config::{CleanupPolicy, Configuration};
+use crate::errors::ISUProcessorError;
+use crate::timestamps;
+use crate::ISUProcessor;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use jester_core::DataSourceMessage;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+// sqlite opens this path as a database that only lives as long as the connection
+pub const MEMORY_STATE_DB: &str = ":memory:";
+
+// BackfillOptions select the runs to process. Paths are run directories or directories holding run
+// directories, runs are picked by their start time with from inclusive and to exclusive
+#[derive(Debug, Clone, Default)]
+pub struct BackfillOptions {
+    pub paths: Vec<PathBuf>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub dry_run: bool, // only list the files that would be processed
+    pub graph: bool,   // write graph outputs, as when Jester has a graph channel
+}
+
+// BackfilledFile is a DAS file the backfill processed, or would have on a dry run
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackfilledFile {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub run_start: DateTime<Utc>,
+    pub outputs: Vec<PathBuf>, // outputs handed to Jester, quarantined rows stay in the output dir
+    pub error: Option<String>, // why the file couldn't be processed
+}
+
+// backfill runs the files of DAS run directories through the processor offline, producing the same
+// outputs the plugin would. Outputs are kept in the output directory instead of being uploaded. A
+// live plugin's state database shouldn't be used as the backfill would advance it, MEMORY_STATE_DB
+// keeps the state for the length of the backfill only. A file that fails is logged and recorded
+// with its error, the others are still processed
+pub fn backfill(
+    mut config: Configuration,
+    options: &BackfillOptions,
+) -> Result<Vec<BackfilledFile>, ISUProcessorError> {
+    config.cleanup = CleanupPolicy::Keep;
+    config.validate()?;
+
+    let timezone = timestamps::das_timezone(config.das_timezone.as_deref())?;
+    let rules = Rules::load(config.file_rules.as_deref())?;
+    let files = run_files(&options.paths, &rules, timezone, options.from, options.to)?;
+    if options.dry_run {
+        return Ok(files);
+    }
+
+    let processor = ISUProcessor::with_config(config)?;
+    crate::create_tables(&*processor.state.lock()?)?;
+    processor.health_check()?;
+    processor.ready.store(true, Ordering::SeqCst);
+
+    let (timeseries_tx, mut timeseries_rx) = unbounded_channel();
+    let (graph_tx, mut graph_rx) = unbounded_channel();
+    let mut processed = vec![];
+    for mut file in files {
+        log::info!("backfilling {:?}", file.path);
+        if let Err(e) = processor.process_file(
+            file.path.clone(),
+            Some(timeseries_tx.clone()),
+            options.graph.then(|| graph_tx.clone()),
+        ) {
+            log::error!("failed to backfill {:?}: {e}", file.path);
+            file.error = Some(e.to_string());
+        }
+
+        file.outputs = received(&mut timeseries_rx);
+        file.outputs.extend(received(&mut graph_rx));
+        processed.push(file);
+    }
+
+    // the runs are over, their summaries are counted with the last file processed
+    processor.end_runs(timeseries_tx, options.graph.then_some(graph_tx))?;
+    if let Some(file) = processed.last_mut() {
+        file.outputs.extend(received(&mut timeseries_rx));
+        file.outputs.extend(received(&mut graph_rx));
+    }
+
+    Ok(processed)
+}
+
+// run_files lists the files of the selected runs in the order they're processed, runs by start
+// time and files by name. Ignored and unclassified files are left out
+pub fn run_files(
+    paths: &[PathBuf],
+    rules: &Rules,
+    tz: Tz,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<BackfilledFile>, ISUProcessorError> {
+    let mut runs = vec![];
+    for path in paths {
+        match directory_start(path, tz) {
+            Some(start) => runs.push((start, path.clone())),
+            None => {
+                let found = runs.len();
+                for entry in fs::read_dir(path)? {
+                    let dir = entry?.path();
+                    if let Some(start) = directory_start(&dir, tz) {
+                        runs.push((start, dir));
+                    }
+                }
+
+                if runs.len() == found {
+                    return Err(ISUProcessorError::NotRunDirectory(
+                        path.to_string_lossy().to_string(),
+                    ));
+                }
+            }
+        }
+    }
+
+    runs.retain(|(start, _)| from.is_none_or(|f| *start >= f) && to.is_none_or(|t| *start < t));
+    runs.sort();
+    runs.dedup();
+
+    let mut files = vec![];
+    for (run_start, dir) in runs {
+        let mut paths = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        for path in paths {
+            match rules.classify(&path) {
+                Ok(FileKind::Ignore) => continue,
+                Ok(kind) => files.push(BackfilledFile {
+                    path,
+                    kind,
+                    run_start,
+                    outputs: vec![],
+                    error: None,
+                }),
+                Err(e) => log::warn!("skipping {path:?}: {e}"),
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+// directory_start is the run start of a DAS run directory, None for any other path
+fn directory_start(path: &Path, tz: Tz) -> Option<DateTime<Utc>> {
+    if !path.is_dir() {
+        return None;
+    }
+
+    timestamps::run_start(path.file_name()?.to_str()?, tz).ok()
+}
+
+fn received(rx: &mut UnboundedReceiver<DataSourceMessage>) -> Vec<PathBuf> {
+    let mut outputs = vec![];
+    while let Ok(message) = rx.try_recv() {
+        if let DataSourceMessage::File((path, _)) = message {
+            outputs.push(path);
+        }
+    }
+
+    outputs
+}
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use jester_isu::backfill::{self, BackfillOptions, MEMORY_STATE_DB};
+use jester_isu::config::Configuration;
+use jester_isu::errors::ISUProcessorError;
+use std::path::PathBuf;
+
+/// Turns historical DAS run directories into the outputs the ISU plugin would have sent, using the
+/// plugin's configuration
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Arguments {
+    /// run directories, or directories of run directories
+    #[clap(required = true, value_name = "RUN_DIR")]
+    paths: Vec<PathBuf>,
+    /// plugin configuration file, ISU_PLUGIN_CONFIG when unset
+    #[clap(short, long, value_name = "FILE")]
+    config_file: Option<PathBuf>,
+    /// directory outputs are written to, the configured output_dir when unset
+    #[clap(short, long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+    /// state database to resume from and record to, state is only kept for the run when unset
+    #[clap(short, long, value_name = "FILE")]
+    state_db: Option<PathBuf>,
+    /// only runs starting at or after this RFC 3339 time
+    #[clap(long, value_parser = parse_time)]
+    from: Option<DateTime<Utc>>,
+    /// only runs starting before this RFC 3339 time
+    #[clap(long, value_parser = parse_time)]
+    to: Option<DateTime<Utc>>,
+    /// list the files that would be processed without writing anything
+    #[clap(long)]
+    dry_run: bool,
+    /// don't write graph outputs
+    #[clap(long)]
+    no_graph: bool,
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    Ok(DateTime::parse_from_rfc3339(time)?.with_timezone(&Utc))
+}
+
+fn main() -> Result<(), ISUProcessorError> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let cli = Arguments::parse();
+
+    let mut config = match cli.config_file {
+        Some(path) => Configuration::load_from(Some(&path))?,
+        None => Configuration::load()?,
+    };
+    config.state_db = cli
+        .state_db
+        .unwrap_or_else(|| PathBuf::from(MEMORY_STATE_DB));
+    if let Some(dir) = cli.output_dir {
+        config.output_dir = dir;
+    }
+
+    let options = BackfillOptions {
+        paths: cli.paths,
+        from: cli.from,
+        to: cli.to,
+        dry_run: cli.dry_run,
+        graph: !cli.no_graph,
+    };
+    let files = backfill::backfill(config, &options)?;
+
+    for file in &files {
+        println!(
+            "{} {} {}",
+            file.run_start.to_rfc3339(),
+            file.kind.as_str(),
+            file.path.display()
+        );
+        for output in &file.outputs {
+            println!("  {}", output.display());
+        }
+        if let Some(error) = &file.error {
+            println!("  failed: {error}");
+        }
+    }
+    log::info!(
+        "{} {} files",
+        if options.dry_run {
+            "would process"
+        } else {
+            "processed"
+        },
+        files.len()
+    );
+
+    let failed = files.iter().filter(|f| f.error.is_some()).count();
+    if failed > 0 {
+        return Err(ISUProcessorError::BackfillError(failed));
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use clap::{Parser, Subcommand};
+use jester_isu::admin::StateAdmin;
+use jester_isu::config::Configuration;
+use jester_isu::errors::ISUProcessorError;
+use std::path::PathBuf;
+
+/// Inspects and changes the ISU plugin's state database. Stop Jester before changing the state,
+/// the running plugin would overwrite it
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Arguments {
+    /// plugin configuration file, ISU_PLUGIN_CONFIG when unset
+    #[clap(short, long, value_name = "FILE")]
+    config_file: Option<PathBuf>,
+    /// state database, the configured state_db when unset
+    #[clap(short, long, value_name = "FILE")]
+    state_db: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// list tracked files, or those of one file or run directory
+    List { path: Option<PathBuf> },
+    /// read a file, or every file of a run directory, again from a row index
+    Rewind {
+        path: PathBuf,
+        /// row to read again from, 0 reads the file as though it was never seen
+        #[clap(short, long, default_value_t = 0)]
+        index: i64,
+    },
+    /// stop tailing a file, or every file of a run directory
+    Finish { path: PathBuf },
+}
+
+fn main() -> Result<(), ISUProcessorError> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let cli = Arguments::parse();
+
+    let mut config = match cli.config_file {
+        Some(path) => Configuration::load_from(Some(&path))?,
+        None => Configuration::load()?,
+    };
+    if let Some(db) = cli.state_db {
+        config.state_db = db;
+    }
+    let admin = StateAdmin::open(config)?;
+
+    match cli.command {
+        Command::List { path } => {
+            println!("path\tposition\tindex\tunprocessed_bytes\trun_start\tupdated_at\tfinished");
+            for file in admin.files(path.as_deref())? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    file.path.display(),
+                    file.last_position_read,
+                    file.last_index,
+                    file.unprocessed_bytes()
+                        .map_or(String::from("missing"), |b| b.to_string()),
+                    file.run_start,
+                    file.updated_at.unwrap_or_default(),
+                    file.finished
+                );
+            }
+        }
+        Command::Rewind { path, index } => {
+            for file in admin.rewind(&path, index)? {
+                println!("rewound {} to row {index}", file.display());
+            }
+        }
+        Command::Finish { path } => {
+            for file in admin.finish(&path)? {
+                println!("finished {}", file.display());
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use crate::errors::ISUProcessorError;
+use crate::runs::RUN_ID_COLUMN;
+use crate::timestamps::{DATETIME_COLUMN, TIMESTAMP_COLUMN};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Mutex;
+
+// ChannelMapping renames a DAS column in the outputs. The unit is recorded on the channel's graph
+// node rather than in the column name
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChannelMapping {
+    pub column: String,
+    pub name: Option<String>, // only the default map leaves it out, keeping the DAS name
+    pub unit: Option<String>,
+}
+
+// the units of the AGN-201 DAS engineering data channels. Downstream consumers look channels up by
+// their DAS names, so they're only renamed when a channel map is configured
+pub const DEFAULT_CHANNEL_MAP: &str = r#"[
+    {"column": "X_Value", "unit": "s"},
+    {"column": "Ch1_CPS", "unit": "CPS"},
+    {"column": "Ch2_Watts", "unit": "W"},
+    {"column": "Ch3_Watts", "unit": "W"},
+    {"column": "CCR_cm", "unit": "cm"},
+    {"column": "FCR_cm", "unit": "cm"},
+    {"column": "Temp"},
+    {"column": "Comment"}
+]"#;
+
+pub struct ChannelMap {
+    mappings: Vec<ChannelMapping>,
+    rename: bool, // columns are written in snake_case, false keeps the DAS names
+    warned: Mutex<HashSet<String>>, // unmapped columns already warned about
+}
+
+impl ChannelMap {
+    // new is a configured channel map, every column is named in snake_case
+    pub fn new(mappings: &[ChannelMapping]) -> Result<ChannelMap, ISUProcessorError> {
+        for m in mappings {
+            match &m.name {
+                Some(name) if snake_case(name) == *name => {}
+                Some(name) => {
+                    return Err(ISUProcessorError::RuleError(format!(
+                        "channel name {name} for column {} must be snake_case",
+                        m.column
+                    )))
+                }
+                None => {
+                    return Err(ISUProcessorError::RuleError(format!(
+                        "column {} has no channel name",
+                        m.column
+                    )))
+                }
+            }
+        }
+
+        Ok(ChannelMap {
+            mappings: mappings.to_vec(),
+            rename: true,
+            warned: Mutex::new(HashSet::new()),
+        })
+    }
+
+    // load reads the channel map from a json file. When no file is configured columns keep their
+    // DAS names and DEFAULT_CHANNEL_MAP only gives their units
+    pub fn load(path: Option<&Path>) -> Result<ChannelMap, ISUProcessorError> {
+        match path {
+            None => Ok(ChannelMap {
+                mappings: serde_json::from_str::<Vec<ChannelMapping>>(DEFAULT_CHANNEL_MAP)?,
+                rename: false,
+                warned: Mutex::new(HashSet::new()),
+            }),
+            Some(path) => {
+                let reader = BufReader::new(File::open(path)?);
+                ChannelMap::new(&serde_json::from_reader::<_, Vec<ChannelMapping>>(reader)?)
+            }
+        }
+    }
+
+    pub fn get(&self, column: &str) -> Option<&ChannelMapping> {
+        self.mappings.iter().find(|m| m.column == column.trim())
+    }
+
+    // name is the output name of a column. Columns missing from a configured map are still written,
+    // under their name in snake_case, but are warned about once so the map can be completed
+    pub fn name(&self, column: &str) -> String {
+        if let Some(name) = self.get(column).and_then(|m| m.name.as_ref()) {
+            return name.clone();
+        }
+        if !self.rename {
+            return column.trim().to_string();
+        }
+
+        // the plugin's own columns don't need mapping
+        if ![DATETIME_COLUMN, TIMESTAMP_COLUMN, RUN_ID_COLUMN].contains(&column) {
+            if let Ok(mut warned) = self.warned.lock() {
+                if warned.insert(column.to_string()) {
+                    log::warn!(
+                        "column {column} is not in the channel map, writing it as {}",
+                        snake_case(column)
+                    );
+                }
+            }
+        }
+
+        snake_case(column)
+    }
+
+    pub fn unit(&self, column: &str) -> Option<&str> {
+        self.get(column).and_then(|m| m.unit.as_deref())
+    }
+
+    // output_headers names the columns of an output, columns that would end up with the same name
+    // are numbered so none of them is lost
+    pub fn output_headers(&self, headers: &[String]) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        for (i, header) in headers.iter().enumerate() {
+            let mut name = self.name(header);
+            if name.is_empty() {
+                name = format!("column_{}", i + 1);
+            }
+
+            let base = name.clone();
+            let mut n = 1;
+            while names.contains(&name) {
+                n += 1;
+                name = format!("{base}_{n}");
+            }
+            names.push(name);
+        }
+
+        names
+    }
+}
+
+// snake_case turns a DAS column name like "Ch1 (CPS)" or "DateTime" into an identifier that needs
+// no quoting downstream, "ch1_cps" and "date_time"
+pub fn snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut previous: Option<char> = None;
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            let boundary = c.is_ascii_uppercase()
+                && previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit());
+            if boundary && !result.ends_with('_') {
+                result.push('_');
+            }
+            result.push(c.to_ascii_lowercase());
+        } else if !result.is_empty() && !result.ends_with('_') {
+            result.push('_');
+        }
+        previous = Some(c);
+    }
+
+    result.trim_end_matches('_').to_string()
+}
@@ -0,0 +1,146 @@
+use crate::errors::ISUProcessorError;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+// FileKind is what a DAS file is classified as, it decides which parser handles the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    Engineering,
+    Temperature,
+    Digitals,
+    Reduced,
+    Events,
+    // explicitly matched files that should never be processed
+    Ignore,
+}
+
+impl FileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::Engineering => "engineering",
+            FileKind::Temperature => "temperature",
+            FileKind::Digitals => "digitals",
+            FileKind::Reduced => "reduced",
+            FileKind::Events => "events",
+            FileKind::Ignore => "ignore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternConfig {
+    Glob(String),
+    Regex(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    pub kind: FileKind,
+    pub file_name: Option<PatternConfig>,
+    pub directory: Option<PatternConfig>,
+}
+
+enum Matcher {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl Matcher {
+    fn new(config: &PatternConfig) -> Result<Matcher, ISUProcessorError> {
+        match config {
+            PatternConfig::Glob(g) => {
+                Ok(Matcher::Glob(glob::Pattern::new(g).map_err(|e| {
+                    ISUProcessorError::RuleError(format!("invalid glob {g}: {e}"))
+                })?))
+            }
+            PatternConfig::Regex(r) => {
+                Ok(Matcher::Regex(regex::Regex::new(r).map_err(|e| {
+                    ISUProcessorError::RuleError(format!("invalid regex {r}: {e}"))
+                })?))
+            }
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Matcher::Glob(g) => g.matches(value),
+            Matcher::Regex(r) => r.is_match(value),
+        }
+    }
+}
+
+struct Rule {
+    kind: FileKind,
+    file_name: Option<Matcher>,
+    directory: Option<Matcher>,
+}
+
+// Rules classifies files by the first rule whose file name and directory patterns both match. A
+// pattern that isn't set matches anything
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+// file names as written by the AGN-201 DAS
+pub const DEFAULT_RULES: &str = r#"[
+    {"kind": "ignore", "file_name": {"glob": "*.tmp"}},
+    {"kind": "events", "file_name": {"glob": "*Events*"}},
+    {"kind": "engineering", "file_name": {"regex": "^(Most|Ch \\d+) Engineering Data.*\\.txt$"}},
+    {"kind": "temperature", "file_name": {"glob": "Temperature*.txt"}},
+    {"kind": "digitals", "file_name": {"glob": "Digitals*.txt"}},
+    {"kind": "reduced", "file_name": {"glob": "Reduced Raw Data*.txt"}}
+]"#;
+
+impl Rules {
+    pub fn new(configs: &[RuleConfig]) -> Result<Rules, ISUProcessorError> {
+        let mut rules = vec![];
+        for config in configs {
+            if config.file_name.is_none() && config.directory.is_none() {
+                return Err(ISUProcessorError::RuleError(format!(
+                    "{} rule must have a file_name or directory pattern",
+                    config.kind.as_str()
+                )));
+            }
+
+            rules.push(Rule {
+                kind: config.kind,
+                file_name: config.file_name.as_ref().map(Matcher::new).transpose()?,
+                directory: config.directory.as_ref().map(Matcher::new).transpose()?,
+            })
+        }
+
+        Ok(Rules { rules })
+    }
+
+    // load reads the rules from a json file, when no file is configured DEFAULT_RULES is used
+    pub fn load(path: Option<&Path>) -> Result<Rules, ISUProcessorError> {
+        match path {
+            None => Rules::new(&serde_json::from_str::<Vec<RuleConfig>>(DEFAULT_RULES)?),
+            Some(path) => {
+                let reader = BufReader::new(File::open(path)?);
+                Rules::new(&serde_json::from_reader::<_, Vec<RuleConfig>>(reader)?)
+            }
+        }
+    }
+
+    pub fn classify(&self, path: &Path) -> Result<FileKind, ISUProcessorError> {
+        let file_name = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .ok_or(ISUProcessorError::BlankPath)?;
+        let directory = path.parent().and_then(|p| p.to_str()).unwrap_or("");
+
+        self.rules
+            .iter()
+            .find(|r| {
+                r.file_name.as_ref().is_none_or(|m| m.matches(file_name))
+                    && r.directory.as_ref().is_none_or(|m| m.matches(directory))
+            })
+            .map(|r| r.kind)
+            .ok_or_else(|| ISUProcessorError::UnclassifiedFile(path.to_string_lossy().to_string()))
+    }
+}
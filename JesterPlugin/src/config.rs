@@ -0,0 +1,330 @@
+use crate::aggregate::Aggregation;
+use crate::classify::FileKind;
+use crate::errors::ISUProcessorError;
+use crate::format::{ChunkLimits, OutputFormat};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// path to a yaml file holding the plugin configuration. This can be the Jester configuration file,
+// in which case the plugin reads its settings from the isu_plugin key
+pub const CONFIG_ENV: &str = "ISU_PLUGIN_CONFIG";
+pub const CONFIG_KEY: &str = "isu_plugin";
+
+// environment variables take precedence over the configuration file
+pub const STATE_DB_ENV: &str = "ISU_STATE_DB";
+pub const OUTPUT_DIR_ENV: &str = "ISU_OUTPUT_DIR";
+pub const OUTPUT_NAME_ENV: &str = "ISU_OUTPUT_NAME";
+pub const CLEANUP_ENV: &str = "ISU_OUTPUT_CLEANUP";
+pub const RETENTION_DAYS_ENV: &str = "ISU_OUTPUT_RETENTION_DAYS";
+pub const TIMEZONE_ENV: &str = "ISU_DAS_TIMEZONE";
+pub const RULES_ENV: &str = "ISU_FILE_RULES";
+pub const VALIDATION_ENV: &str = "ISU_VALIDATION_RULES";
+pub const CHANNEL_MAP_ENV: &str = "ISU_CHANNEL_MAP";
+// yaml or json map of file kind to aggregation, e.g. {engineering: {interval_seconds: 10}}
+pub const AGGREGATION_ENV: &str = "ISU_AGGREGATION";
+// yaml or json map of file kind to output format, e.g. {engineering: parquet}
+pub const FORMATS_ENV: &str = "ISU_OUTPUT_FORMATS";
+pub const CHUNK_ROWS_ENV: &str = "ISU_CHUNK_ROWS";
+pub const CHUNK_BYTES_ENV: &str = "ISU_CHUNK_BYTES";
+pub const RUN_IDLE_TIMEOUT_ENV: &str = "ISU_RUN_IDLE_TIMEOUT";
+
+// run times in output names can't contain the colons of RFC 3339 on every filesystem
+const NAME_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+// the kind and source run summaries are named with
+const SUMMARY: &str = "summary";
+
+// CleanupPolicy decides what happens to an output once it has been handed to Jester
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupPolicy {
+    // Jester removes the output once it's uploaded
+    Delete,
+    // outputs are never removed
+    Keep,
+    // outputs are kept for retention_days and then removed by the plugin
+    Retain,
+}
+
+impl CleanupPolicy {
+    pub fn delete_uploaded(&self) -> bool {
+        *self == CleanupPolicy::Delete
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Configuration {
+    pub state_db: PathBuf,
+    pub output_dir: PathBuf,
+    // output file name without extension, see output_path for the placeholders
+    pub output_name: String,
+    pub cleanup: CleanupPolicy,
+    pub retention_days: u64,
+    pub das_timezone: Option<String>, // IANA name of the DAS clock's timezone, UTC when unset
+    pub file_rules: Option<PathBuf>,  // json classification rules, the DAS defaults when unset
+    pub validation_rules: Option<PathBuf>, // json channel validation rules, the DAS defaults when unset
+    pub channel_map: Option<PathBuf>, // json output column names and units, the DAS channels when unset
+    pub aggregation: HashMap<FileKind, Aggregation>, // file kinds sent as windows, the rest are sent raw
+    pub formats: HashMap<FileKind, OutputFormat>, // file kinds not listed are written as csv
+    pub chunk_rows: Option<u64>,  // rows an output holds before the next chunk is started
+    pub chunk_bytes: Option<u64>, // bytes of row data an output holds before the next chunk is started
+    pub run_idle_timeout_seconds: u64, // a run without new rows for this long is closed, 0 never closes idle runs
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            state_db: PathBuf::from("./agn201_plugin"),
+            output_dir: PathBuf::from("."),
+            output_name: String::from("{uuid}"),
+            cleanup: CleanupPolicy::Delete,
+            retention_days: 7,
+            das_timezone: None,
+            file_rules: None,
+            validation_rules: None,
+            channel_map: None,
+            aggregation: HashMap::new(),
+            formats: HashMap::new(),
+            chunk_rows: None,
+            chunk_bytes: None,
+            run_idle_timeout_seconds: 60 * 60,
+        }
+    }
+}
+
+// the Jester configuration file has plenty of other keys, only ours are read
+#[derive(Deserialize)]
+struct JesterConfiguration {
+    isu_plugin: Option<Configuration>,
+}
+
+impl Configuration {
+    // load reads the configuration file named by ISU_PLUGIN_CONFIG, if any, and applies the
+    // environment overrides on top
+    pub fn load() -> Result<Configuration, ISUProcessorError> {
+        let path = std::env::var(CONFIG_ENV).ok().map(PathBuf::from);
+        Configuration::load_from(path.as_deref())
+    }
+
+    // load_from reads the given configuration file instead of the one named by ISU_PLUGIN_CONFIG
+    pub fn load_from(path: Option<&Path>) -> Result<Configuration, ISUProcessorError> {
+        let mut config = match path {
+            None => Configuration::default(),
+            Some(path) => Configuration::from_file(path)?,
+        };
+
+        if let Ok(v) = std::env::var(STATE_DB_ENV) {
+            config.state_db = PathBuf::from(v);
+        }
+        if let Ok(v) = std::env::var(OUTPUT_DIR_ENV) {
+            config.output_dir = PathBuf::from(v);
+        }
+        if let Ok(v) = std::env::var(OUTPUT_NAME_ENV) {
+            config.output_name = v;
+        }
+        if let Ok(v) = std::env::var(CLEANUP_ENV) {
+            config.cleanup = serde_yaml::from_str(&v).map_err(|_| {
+                ISUProcessorError::ConfigError(format!(
+                    "{CLEANUP_ENV} must be delete, keep or retain, not {v}"
+                ))
+            })?;
+        }
+        if let Ok(v) = std::env::var(RETENTION_DAYS_ENV) {
+            config.retention_days = v.parse().map_err(|_| {
+                ISUProcessorError::ConfigError(format!(
+                    "{RETENTION_DAYS_ENV} must be a number of days"
+                ))
+            })?;
+        }
+        if let Ok(v) = std::env::var(TIMEZONE_ENV) {
+            config.das_timezone = Some(v);
+        }
+        if let Ok(v) = std::env::var(RULES_ENV) {
+            config.file_rules = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var(VALIDATION_ENV) {
+            config.validation_rules = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var(CHANNEL_MAP_ENV) {
+            config.channel_map = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = std::env::var(AGGREGATION_ENV) {
+            config.aggregation = serde_yaml::from_str(&v).map_err(|e| {
+                ISUProcessorError::ConfigError(format!("{AGGREGATION_ENV} is not a valid aggregation: {e}"))
+            })?;
+        }
+        if let Ok(v) = std::env::var(FORMATS_ENV) {
+            config.formats = serde_yaml::from_str(&v).map_err(|e| {
+                ISUProcessorError::ConfigError(format!("{FORMATS_ENV} must map file kinds to csv, csv_gzip or parquet: {e}"))
+            })?;
+        }
+        if let Ok(v) = std::env::var(CHUNK_ROWS_ENV) {
+            config.chunk_rows = Some(v.parse().map_err(|_| {
+                ISUProcessorError::ConfigError(format!("{CHUNK_ROWS_ENV} must be a number of rows"))
+            })?);
+        }
+        if let Ok(v) = std::env::var(CHUNK_BYTES_ENV) {
+            config.chunk_bytes = Some(v.parse().map_err(|_| {
+                ISUProcessorError::ConfigError(format!("{CHUNK_BYTES_ENV} must be a number of bytes"))
+            })?);
+        }
+        if let Ok(v) = std::env::var(RUN_IDLE_TIMEOUT_ENV) {
+            config.run_idle_timeout_seconds = v.parse().map_err(|_| {
+                ISUProcessorError::ConfigError(format!(
+                    "{RUN_IDLE_TIMEOUT_ENV} must be a number of seconds"
+                ))
+            })?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    // from_file reads the isu_plugin section of a Jester configuration file, or the whole file if
+    // it's a configuration of its own
+    pub fn from_file(path: &Path) -> Result<Configuration, ISUProcessorError> {
+        let value: serde_yaml::Value = serde_yaml::from_reader(File::open(path)?)?;
+
+        if value.get(CONFIG_KEY).is_some() {
+            let jester: JesterConfiguration = serde_yaml::from_value(value)?;
+            Ok(jester.isu_plugin.unwrap_or_default())
+        } else {
+            Ok(serde_yaml::from_value(value)?)
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ISUProcessorError> {
+        // every output has to have a unique name, and the uuid is how orphaned outputs are recognized
+        if !self.output_name.contains("{uuid}") {
+            return Err(ISUProcessorError::ConfigError(format!(
+                "output name {} must contain {{uuid}}",
+                self.output_name
+            )));
+        }
+
+        if self.output_name.contains(['/', '\\']) {
+            return Err(ISUProcessorError::ConfigError(format!(
+                "output name {} must not contain a path separator, set output_dir instead",
+                self.output_name
+            )));
+        }
+
+        if self.chunk_rows == Some(0) || self.chunk_bytes == Some(0) {
+            return Err(ISUProcessorError::ConfigError(String::from(
+                "chunk limits must be greater than 0",
+            )));
+        }
+
+        for (kind, aggregation) in &self.aggregation {
+            // events are free text, there's nothing to aggregate
+            if *kind == FileKind::Events || *kind == FileKind::Ignore {
+                return Err(ISUProcessorError::ConfigError(format!(
+                    "{} files can't be aggregated",
+                    kind.as_str()
+                )));
+            }
+
+            if aggregation.interval_seconds.is_nan() || aggregation.interval_seconds < 0.001 {
+                return Err(ISUProcessorError::ConfigError(format!(
+                    "aggregation interval of {} files must be at least a millisecond",
+                    kind.as_str()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn format(&self, kind: FileKind) -> OutputFormat {
+        self.formats.get(&kind).copied().unwrap_or_default()
+    }
+
+    pub fn run_idle_timeout(&self) -> Option<Duration> {
+        if self.run_idle_timeout_seconds == 0 {
+            return None;
+        }
+
+        Duration::from_std(std::time::Duration::from_secs(self.run_idle_timeout_seconds)).ok()
+    }
+
+    pub fn chunk_limits(&self) -> ChunkLimits {
+        ChunkLimits {
+            rows: self.chunk_rows,
+            bytes: self.chunk_bytes,
+        }
+    }
+
+    // output_path names a new output file for the source file. The name pattern can use {uuid},
+    // {kind} (the source's file kind), {run_time} (the UTC start of the run), {run} (the run
+    // directory), {source} (the source file name without extension) and {sequence} (the number of
+    // the output among the source's outputs). When outputs are chunked the sequence is always in
+    // the name, it's appended if the pattern doesn't place it
+    pub fn output_path(
+        &self,
+        kind: FileKind,
+        run_start: DateTime<Utc>,
+        source: &Path,
+        sequence: i64,
+        extension: &str,
+    ) -> PathBuf {
+        let stem = source
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.named_path(kind.as_str(), run_start, source.parent(), &stem, Some(sequence), extension)
+    }
+
+    // summary_path names the summary of a run, it's named like an output with a kind and source of
+    // summary
+    pub fn summary_path(&self, run_start: DateTime<Utc>, run_directory: &Path) -> PathBuf {
+        self.named_path(SUMMARY, run_start, Some(run_directory), SUMMARY, None, "json")
+    }
+
+    fn named_path(
+        &self,
+        kind: &str,
+        run_start: DateTime<Utc>,
+        run_directory: Option<&Path>,
+        stem: &str,
+        sequence: Option<i64>,
+        extension: &str,
+    ) -> PathBuf {
+        let run = run_directory
+            .and_then(|p| p.file_name())
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let name = self
+            .output_name
+            .replace("{uuid}", Uuid::new_v4().to_string().as_str())
+            .replace("{kind}", kind)
+            .replace(
+                "{run_time}",
+                run_start.format(NAME_TIME_FORMAT).to_string().as_str(),
+            )
+            .replace("{run}", run.as_str())
+            .replace("{source}", stem)
+            .replace("{sequence}", format!("{:06}", sequence.unwrap_or(0)).as_str());
+        let name = match sequence {
+            Some(s) if self.chunk_limits().is_set() && !self.output_name.contains("{sequence}") => {
+                format!("{name}_{s:06}")
+            }
+            _ => name,
+        };
+
+        self.output_dir.join(format!("{name}.{extension}"))
+    }
+}
+
+// contains_uuid is true for file names that could have come from output_path
+pub fn contains_uuid(name: &str) -> bool {
+    name.char_indices().any(|(i, _)| {
+        name.get(i..i + 36)
+            .is_some_and(|s| Uuid::parse_str(s).is_ok())
+    })
+}
@@ -9,7 +9,7 @@ pub enum ISUProcessorError {
     Unknown,
     #[error("blank path supplied")]
     BlankPath,
-    #[error("io error")]
+    #[error("io error {0}")]
     IOError(#[from] io::Error),
     #[error("runtime error")]
     ThreadError,
@@ -21,12 +21,46 @@ pub enum ISUProcessorError {
     CSVError(#[from] csv::Error),
     #[error("time parsing error")]
     TimeParsingError(#[from] chrono::ParseError),
+    #[error("timezone error {0}")]
+    TimezoneError(String),
+    #[error("classification rule error {0}")]
+    RuleError(String),
+    #[error("no classification rule matches {0}")]
+    UnclassifiedFile(String),
     #[error("no channel error")]
     NoChannelError,
+    #[error("channel send error")]
+    ChannelSendError,
     #[error("parse int error")]
     ParseIntError(#[from] TryFromIntError),
     #[error("rusqlite error {0}")]
     Rusqlite(#[from] rusqlite::Error),
+    #[error("lvm header error {0}")]
+    LvmHeaderError(String),
+    #[error("json error {0}")]
+    JSONError(#[from] serde_json::Error),
+    #[error("yaml error {0}")]
+    YAMLError(#[from] serde_yaml::Error),
+    #[error("parquet error {0}")]
+    ParquetError(#[from] parquet::errors::ParquetError),
+    #[error("arrow error {0}")]
+    ArrowError(#[from] arrow_schema::ArrowError),
+    #[error("configuration error {0}")]
+    ConfigError(String),
+    #[error("migration error {0}")]
+    MigrationError(String),
+    #[error("health check failed: {0}")]
+    HealthCheckError(String),
+    #[error("processor unavailable: {0}")]
+    Unavailable(String),
+    #[error("processor hasn't been initialized")]
+    NotReady,
+    #[error("no run directories in {0}")]
+    NotRunDirectory(String),
+    #[error("{0} files failed to backfill")]
+    BackfillError(usize),
+    #[error("state error {0}")]
+    StateError(String),
 }
 
 impl From<ISUProcessorError> for ProcessorError {
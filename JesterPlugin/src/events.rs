@@ -0,0 +1,186 @@
+use crate::format::ColumnType;
+use crate::timestamps;
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+use std::sync::OnceLock;
+
+pub const EVENT_HEADERS: [&str; 7] = [
+    "Event",
+    "Index",
+    "DateTime",
+    "EventTime",
+    "Category",
+    "Component",
+    "Text",
+];
+
+pub const EVENT_TYPES: [ColumnType; 7] = [
+    ColumnType::Text,
+    ColumnType::Integer,
+    ColumnType::Timestamp,
+    ColumnType::Timestamp,
+    ColumnType::Text,
+    ColumnType::Text,
+    ColumnType::Text,
+];
+
+// formats the DAS has been seen to write event times in, tried in order
+const DATE_TIME_FORMATS: [&str; 5] = [
+    "%m/%d/%Y %I:%M:%S %p",
+    "%m/%d/%Y %I:%M:%S%.f %p",
+    "%m/%d/%Y %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+];
+const TIME_FORMATS: [&str; 2] = ["%I:%M:%S %p", "%H:%M:%S%.f"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventCategory {
+    RodMotion,
+    Scram,
+    Alarm,
+    OperatorNote,
+    ModeChange,
+    Other,
+}
+
+impl EventCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventCategory::RodMotion => "rod_motion",
+            EventCategory::Scram => "scram",
+            EventCategory::Alarm => "alarm",
+            EventCategory::OperatorNote => "operator_note",
+            EventCategory::ModeChange => "mode_change",
+            EventCategory::Other => "other",
+        }
+    }
+
+    // categorize by keyword, a scram mentions rods and alarms as well so it is checked first
+    fn from_text(text: &str) -> EventCategory {
+        let keywords = keywords();
+        if keywords.scram.is_match(text) {
+            EventCategory::Scram
+        } else if keywords.alarm.is_match(text) {
+            EventCategory::Alarm
+        } else if keywords.rod.is_match(text) && keywords.motion.is_match(text) {
+            EventCategory::RodMotion
+        } else if keywords.mode.is_match(text) {
+            EventCategory::ModeChange
+        } else if keywords.note.is_match(text) {
+            EventCategory::OperatorNote
+        } else {
+            EventCategory::Other
+        }
+    }
+}
+
+// Keywords are matched as whole words, so a rod startup isn't rod motion and stripped isn't a trip.
+// A shutdown isn't always a scram, the DAS logs planned ones as well
+struct Keywords {
+    scram: Regex,
+    alarm: Regex,
+    rod: Regex,
+    motion: Regex,
+    mode: Regex,
+    note: Regex,
+}
+
+fn keywords() -> &'static Keywords {
+    static KEYWORDS: OnceLock<Keywords> = OnceLock::new();
+    KEYWORDS.get_or_init(|| {
+        let words = |pattern: &str| {
+            Regex::new(&format!(r"(?i)\b(?:{pattern})\b")).expect("invalid keyword regex")
+        };
+        Keywords {
+            scram: words(r"scram(?:s|med)?|trip(?:s|ped)?"),
+            alarm: words(r"alarms?|warnings?|faults?|limit exceeded"),
+            rod: words(r"rods?"),
+            motion: words(r"insert(?:s|ed|ing|ion)?|withdr(?:aw|aws|awn|awing|awal|ew)|motion|mov(?:e|es|ed|ing|ement)|up|down|position(?:s|ed|ing)?"),
+            mode: words(r"modes?"),
+            note: words(r"notes?|comments?|operator"),
+        }
+    })
+}
+
+fn component_regex() -> &'static Regex {
+    static COMPONENT: OnceLock<Regex> = OnceLock::new();
+    COMPONENT.get_or_init(|| {
+        Regex::new(r"(?i)\b((?:safety|coarse|fine|control|regulating)\s+(?:control\s+)?rod(?:\s*#?\d+)?|rod\s*#?\d+|ch(?:annel)?\s*\d+|ccr|fcr|scr)\b")
+            .expect("invalid component regex")
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub raw: String,
+    pub time: Option<DateTime<Utc>>,
+    pub category: EventCategory,
+    pub component: Option<String>,
+    pub text: String,
+}
+
+impl Event {
+    // parse a single line of the events log, blank lines return None. Lines are a timestamp followed
+    // by free text, separated by a tab or comma. Times without a date are on the day the run started
+    pub fn parse(line: &str, run_start: DateTime<Utc>, tz: Tz) -> Option<Event> {
+        let raw = line
+            .trim_end_matches(['\r', '\n', '\t', ',', ' '])
+            .trim_start();
+        if raw.is_empty() {
+            return None;
+        }
+
+        let (time, text) = match raw.split_once(['\t', ',']) {
+            Some((first, rest)) => match parse_time(first.trim(), run_start, tz) {
+                Some(t) => (Some(t), rest.trim()),
+                None => (None, raw),
+            },
+            None => (None, raw),
+        };
+
+        Some(Event {
+            raw: raw.to_string(),
+            time,
+            category: EventCategory::from_text(text),
+            component: component_regex().find(text).map(|m| m.as_str().to_string()),
+            text: text.to_string(),
+        })
+    }
+
+    pub fn record(&self, index: i32, run_start: &str) -> [String; 7] {
+        [
+            self.raw.clone(),
+            format!("{index}"),
+            run_start.to_string(),
+            timestamps::format_time(self.time),
+            self.category.as_str().to_string(),
+            self.component.clone().unwrap_or_default(),
+            self.text.clone(),
+        ]
+    }
+}
+
+fn parse_time(value: &str, run_start: DateTime<Utc>, tz: Tz) -> Option<DateTime<Utc>> {
+    for format in DATE_TIME_FORMATS {
+        if let Ok(t) = NaiveDateTime::parse_from_str(value, format) {
+            return timestamps::to_utc(t, tz).ok();
+        }
+    }
+
+    let start = run_start.with_timezone(&tz).naive_local();
+    for format in TIME_FORMATS {
+        if let Ok(t) = NaiveTime::parse_from_str(value, format) {
+            // a time of day earlier than the run start means the run went past midnight
+            let mut local = start.date().and_time(t);
+            if local < start - Duration::minutes(1) {
+                local += Duration::days(1);
+            }
+
+            return timestamps::to_utc(local, tz).ok();
+        }
+    }
+
+    None
+}
@@ -0,0 +1,276 @@
+use crate::errors::ISUProcessorError;
+use crate::journal;
+use arrow_array::{
+    ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray, TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::DateTime;
+use csv::StringRecord;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// rows are buffered and written to parquet files in row groups of this size
+const ROW_GROUP_ROWS: usize = 8192;
+
+// OutputFormat is the file format outputs of a file kind are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    CsvGzip,
+    Parquet,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::CsvGzip => "csv.gz",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+// ColumnType is the type a column is written as in typed formats, csv outputs are all text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Timestamp,
+    Float,
+    Integer,
+    Text,
+}
+
+impl ColumnType {
+    fn data_type(&self) -> DataType {
+        match self {
+            ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            ColumnType::Float => DataType::Float64,
+            ColumnType::Integer => DataType::Int64,
+            ColumnType::Text => DataType::Utf8,
+        }
+    }
+}
+
+// output_stem is the file name of an output without its format's extension
+pub fn output_stem(output: &Path) -> String {
+    let name = output
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    [
+        OutputFormat::CsvGzip,
+        OutputFormat::Csv,
+        OutputFormat::Parquet,
+    ]
+    .iter()
+    .find_map(|f| name.strip_suffix(format!(".{}", f.extension()).as_str()))
+    .map(String::from)
+    .unwrap_or_else(|| {
+        output
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    })
+}
+
+// sibling_path names a file written alongside an output, e.g. {stem}_quarantine.csv
+pub fn sibling_path(output: &Path, suffix: &str, extension: &str) -> PathBuf {
+    output.with_file_name(format!("{}_{suffix}.{extension}", output_stem(output)))
+}
+
+// ChunkLimits are how much an output holds before the next one is started. Bytes are counted as
+// the size of the rows as text, before any compression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkLimits {
+    pub rows: Option<u64>,
+    pub bytes: Option<u64>,
+}
+
+impl ChunkLimits {
+    pub fn is_set(&self) -> bool {
+        self.rows.is_some() || self.bytes.is_some()
+    }
+
+    pub fn reached(&self, writer: &RowWriter) -> bool {
+        self.rows.is_some_and(|r| writer.rows >= r) || self.bytes.is_some_and(|b| writer.bytes >= b)
+    }
+}
+
+// RowWriter writes rows to the part file of an output in the output's format, counting what it
+// has written
+pub struct RowWriter {
+    rows: u64,
+    bytes: u64,
+    writer: Writer,
+}
+
+enum Writer {
+    Csv(csv::Writer<File>),
+    CsvGzip(csv::Writer<GzEncoder<File>>),
+    Parquet(ParquetRows),
+}
+
+impl RowWriter {
+    pub fn create(
+        path: &Path,
+        format: OutputFormat,
+        headers: &[String],
+        types: &[ColumnType],
+    ) -> Result<RowWriter, ISUProcessorError> {
+        let file = journal::create_output(path)?;
+
+        let writer = match format {
+            OutputFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(file);
+                writer.write_record(headers)?;
+                Writer::Csv(writer)
+            }
+            OutputFormat::CsvGzip => {
+                let mut writer = csv::WriterBuilder::new()
+                    .flexible(true)
+                    .from_writer(GzEncoder::new(file, Compression::default()));
+                writer.write_record(headers)?;
+                Writer::CsvGzip(writer)
+            }
+            OutputFormat::Parquet => Writer::Parquet(ParquetRows::new(file, headers, types)?),
+        };
+
+        Ok(RowWriter {
+            rows: 0,
+            bytes: 0,
+            writer,
+        })
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn write(&mut self, record: &StringRecord) -> Result<(), ISUProcessorError> {
+        match &mut self.writer {
+            Writer::Csv(w) => w.write_byte_record(record.as_byte_record())?,
+            Writer::CsvGzip(w) => w.write_byte_record(record.as_byte_record())?,
+            Writer::Parquet(w) => w.write(record)?,
+        }
+
+        // the fields, their separators and the line ending
+        self.rows += 1;
+        self.bytes += (record.as_byte_record().as_slice().len() + record.len()) as u64;
+        Ok(())
+    }
+
+    // finish completes the output and makes sure the part file is on disk before the state that
+    // references it is committed
+    pub fn finish(self) -> Result<(), ISUProcessorError> {
+        let file = match self.writer {
+            Writer::Csv(w) => w.into_inner().map_err(|e| e.into_error())?,
+            Writer::CsvGzip(w) => w.into_inner().map_err(|e| e.into_error())?.finish()?,
+            Writer::Parquet(w) => w.finish()?,
+        };
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+// ParquetRows buffers rows into typed row groups. Values that don't parse as their column's type
+// are written as nulls, validation is what catches bad readings
+pub struct ParquetRows {
+    writer: ArrowWriter<File>,
+    schema: Arc<Schema>,
+    types: Vec<ColumnType>,
+    rows: Vec<StringRecord>,
+}
+
+impl ParquetRows {
+    fn new(
+        file: File,
+        headers: &[String],
+        types: &[ColumnType],
+    ) -> Result<ParquetRows, ISUProcessorError> {
+        // columns without a type are written as text
+        let types: Vec<ColumnType> = (0..headers.len())
+            .map(|i| types.get(i).copied().unwrap_or(ColumnType::Text))
+            .collect();
+        let fields: Vec<Field> = headers
+            .iter()
+            .zip(&types)
+            .map(|(h, t)| Field::new(h, t.data_type(), true))
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        let properties = WriterProperties::builder()
+            .set_compression(parquet::basic::Compression::SNAPPY)
+            .build();
+
+        Ok(ParquetRows {
+            writer: ArrowWriter::try_new(file, schema.clone(), Some(properties))?,
+            schema,
+            types,
+            rows: vec![],
+        })
+    }
+
+    fn write(&mut self, record: &StringRecord) -> Result<(), ISUProcessorError> {
+        self.rows.push(record.clone());
+        if self.rows.len() >= ROW_GROUP_ROWS {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ISUProcessorError> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let columns: Vec<ArrayRef> = self
+            .types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| column(t, self.rows.iter().map(|r| r.get(i).unwrap_or("").trim())))
+            .collect();
+        self.writer
+            .write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<File, ISUProcessorError> {
+        self.flush()?;
+        Ok(self.writer.into_inner()?)
+    }
+}
+
+fn column<'a>(column_type: &ColumnType, values: impl Iterator<Item = &'a str>) -> ArrayRef {
+    match column_type {
+        ColumnType::Timestamp => Arc::new(
+            values
+                .map(|v| {
+                    DateTime::parse_from_rfc3339(v)
+                        .ok()
+                        .map(|t| t.timestamp_micros())
+                })
+                .collect::<TimestampMicrosecondArray>()
+                .with_timezone("UTC"),
+        ),
+        ColumnType::Float => Arc::new(
+            values
+                .map(|v| v.parse::<f64>().ok())
+                .collect::<Float64Array>(),
+        ),
+        ColumnType::Integer => Arc::new(
+            values
+                .map(|v| v.parse::<i64>().ok())
+                .collect::<Int64Array>(),
+        ),
+        ColumnType::Text => Arc::new(values.map(Some).collect::<StringArray>()),
+    }
+}
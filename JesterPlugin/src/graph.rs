@@ -0,0 +1,189 @@
+use crate::channels::ChannelMap;
+use crate::errors::ISUProcessorError;
+use crate::format;
+use crate::manifest::Manifest;
+use crate::runs::Run;
+use crate::timestamps;
+use crate::{journal, ISUFile};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub const RUN_METATYPE: &str = "ExperimentRun";
+pub const CHANNEL_METATYPE: &str = "SensorChannel";
+pub const SOURCE_FILE_METATYPE: &str = "DASFile";
+pub const TIMESERIES_FILE_METATYPE: &str = "TimeseriesFile";
+
+// columns the plugin adds or that index the data, these are not sensor channels
+pub const NON_CHANNEL_COLUMNS: [&str; 4] = ["X_Value", "Comment", "DateTime", "Timestamp"];
+
+// GraphRecord is a single node or edge sent to DeepLynx. Nodes are keyed by a stable id so that
+// sending the same run, channel or source file again updates the existing node
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "record_type", rename_all = "snake_case")]
+pub enum GraphRecord {
+    Node {
+        id: String,
+        metatype: String,
+        properties: Map<String, Value>,
+    },
+    Edge {
+        relationship: String,
+        origin_id: String,
+        destination_id: String,
+    },
+}
+
+fn node(id: String, metatype: &str, properties: Value) -> GraphRecord {
+    GraphRecord::Node {
+        id,
+        metatype: metatype.to_string(),
+        properties: match properties {
+            Value::Object(m) => m,
+            _ => Map::new(),
+        },
+    }
+}
+
+fn edge(relationship: &str, origin_id: &str, destination_id: &str) -> GraphRecord {
+    GraphRecord::Edge {
+        relationship: relationship.to_string(),
+        origin_id: origin_id.to_string(),
+        destination_id: destination_id.to_string(),
+    }
+}
+
+// build_records creates the run, channel, source file and timeseries file nodes for a single
+// processing pass along with the edges tying them together
+pub fn build_records(
+    run_directory: &str,
+    db_file: &ISUFile,
+    output: &Path,
+    channel_map: &ChannelMap,
+) -> Vec<GraphRecord> {
+    let run_id = format!("run:{run_directory}");
+    let source_id = format!("file:{}", db_file.path);
+    let output_name = output
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let output_id = format!("timeseries:{output_name}");
+
+    let mut records = vec![
+        node(
+            run_id.clone(),
+            RUN_METATYPE,
+            json!({"run_id": run_directory, "run_directory": run_directory, "start_time": db_file.time}),
+        ),
+        node(
+            source_id.clone(),
+            SOURCE_FILE_METATYPE,
+            json!({"path": db_file.path}),
+        ),
+        node(
+            output_id.clone(),
+            TIMESERIES_FILE_METATYPE,
+            json!({"file_name": output_name}),
+        ),
+        edge("belongs_to", &source_id, &run_id),
+        edge("produced_by", &output_id, &run_id),
+        edge("derived_from", &output_id, &source_id),
+    ];
+
+    // only LVM files have sensor channels, the events log is free text
+    let segment = match &db_file.lvm_header {
+        None => return records,
+        Some(h) => &h.segment,
+    };
+    let channels = db_file
+        .headers
+        .split(',')
+        .filter(|h| !h.is_empty() && !NON_CHANNEL_COLUMNS.contains(h));
+
+    // LVM segment fields are ordered by channel, which is the column order minus the X_Value column.
+    // Channels are named as they are in the outputs, the channel map's unit wins over the LVM's
+    for (i, column) in channels.enumerate() {
+        let channel = channel_map.name(column);
+        let channel_id = format!("channel:{channel}");
+        let properties = json!({
+            "name": channel,
+            "column": column,
+            "unit": channel_map.unit(column).or(segment.unit(i)),
+            "sample_rate": segment.sample_rate(i),
+        });
+
+        records.push(node(channel_id.clone(), CHANNEL_METATYPE, properties));
+        records.push(edge("recorded_in", &channel_id, &run_id));
+        records.push(edge("contains", &output_id, &channel_id));
+    }
+
+    records
+}
+
+// summary_records update the run node with the summary of a run that has ended. run_complete is
+// only ever sent as true, a run without it is still being recorded
+pub fn summary_records(run: &Run, closed_at: DateTime<Utc>) -> Vec<GraphRecord> {
+    let stats = &run.stats;
+    let channels: Map<String, Value> = stats
+        .channels
+        .iter()
+        .map(|(name, s)| {
+            let summary = json!({"min": s.min, "max": s.max, "mean": s.mean(), "count": s.count});
+            (name.clone(), summary)
+        })
+        .collect();
+    let peak = stats.peak_power.as_ref();
+
+    vec![node(
+        format!("run:{}", run.name()),
+        RUN_METATYPE,
+        json!({
+            "run_id": run.name(),
+            "run_directory": run.name(),
+            "start_time": timestamps::format_time(Some(run.run_start)),
+            "end_time": timestamps::format_time(Some(run.end())),
+            "duration_seconds": (run.end() - run.run_start).num_milliseconds() as f64 / 1000.0,
+            "run_complete": true,
+            "closed_at": timestamps::format_time(Some(closed_at)),
+            "accepted_rows": stats.accepted_rows,
+            "rejected_rows": stats.rejected_rows,
+            "peak_power_watts": peak.map(|p| p.watts),
+            "peak_power_channel": peak.map(|p| p.channel.clone()),
+            "peak_power_time": peak.and_then(|p| p.time).map(|t| timestamps::format_time(Some(t))),
+            "channels": channels,
+        }),
+    )]
+}
+
+// manifest_records update the timeseries file node of an output with its manifest
+pub fn manifest_records(manifest: &Manifest) -> Result<Vec<GraphRecord>, ISUProcessorError> {
+    Ok(vec![node(
+        format!("timeseries:{}", manifest.output),
+        TIMESERIES_FILE_METATYPE,
+        serde_json::to_value(manifest)?,
+    )])
+}
+
+// graph_path is where the graph records for a timeseries output are written, next to the output
+pub fn graph_path(output: &Path) -> Result<PathBuf, ISUProcessorError> {
+    if output.file_name().is_none() {
+        return Err(ISUProcessorError::BlankPath);
+    }
+
+    Ok(format::sibling_path(output, "graph", "json"))
+}
+
+// write_records writes the graph records as a json array to the part file of path
+pub fn write_records(records: &[GraphRecord], path: &Path) -> Result<(), ISUProcessorError> {
+    let mut writer = BufWriter::new(journal::create_output(path)?);
+    serde_json::to_writer(&mut writer, records)?;
+    writer.flush()?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use crate::errors::ISUProcessorError;
+use crate::migrations;
+use rusqlite::{Connection, DatabaseName, Transaction, TransactionBehavior};
+
+// the tables and columns the plugin reads and writes
+const SCHEMA: [(&str, &[&str]); 6] = [
+    (
+        "isu",
+        &[
+            "path",
+            "last_position_read",
+            "headers",
+            "time",
+            "last_index",
+            "accepted_rows",
+            "rejected_rows",
+            "output_sequence",
+            "updated_at",
+            "finished",
+        ],
+    ),
+    ("isu_lvm_header", &["path", "header"]),
+    (
+        "isu_identity",
+        &["path", "inode", "size", "head_len", "head_checksum"],
+    ),
+    ("isu_pending_output", &["path", "source", "channel", "sent"]),
+    ("isu_window", &["path", "window"]),
+    (
+        "isu_run",
+        &["directory", "run_start", "last_activity", "stats", "closed_at"],
+    ),
+];
+
+// check verifies the state database can be written to and has the schema this version of the
+// plugin expects. Files are only accepted once it has passed
+pub fn check(db: &Connection) -> Result<(), ISUProcessorError> {
+    check_writable(db)?;
+    check_schema(db)
+}
+
+fn check_writable(db: &Connection) -> Result<(), ISUProcessorError> {
+    if db.is_readonly(DatabaseName::Main)? {
+        return Err(ISUProcessorError::HealthCheckError(String::from(
+            "state database is read only",
+        )));
+    }
+
+    // an immediate transaction takes the write lock, so this also fails if another process holds
+    // the database. The write is rolled back when the transaction is dropped
+    let tx = Transaction::new_unchecked(db, TransactionBehavior::Immediate).map_err(|e| {
+        ISUProcessorError::HealthCheckError(format!("unable to lock state database: {e}"))
+    })?;
+    tx.execute("CREATE TABLE isu_health_check (checked integer)", [])
+        .map_err(|e| {
+            ISUProcessorError::HealthCheckError(format!("unable to write state database: {e}"))
+        })?;
+
+    Ok(())
+}
+
+fn check_schema(db: &Connection) -> Result<(), ISUProcessorError> {
+    let version = migrations::current_version(db)?;
+    if version != migrations::latest_version() {
+        return Err(ISUProcessorError::HealthCheckError(format!(
+            "state database is at schema version {version}, expected {}",
+            migrations::latest_version()
+        )));
+    }
+
+    let mut stmt = db.prepare("SELECT name FROM pragma_table_info(?1)")?;
+
+    for (table, columns) in SCHEMA {
+        let existing = stmt
+            .query_map([table], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        if existing.is_empty() {
+            return Err(ISUProcessorError::HealthCheckError(format!(
+                "state database is missing table {table}"
+            )));
+        }
+
+        if let Some(column) = columns.iter().find(|c| !existing.iter().any(|e| e == *c)) {
+            return Err(ISUProcessorError::HealthCheckError(format!(
+                "state database table {table} is missing column {column}"
+            )));
+        }
+    }
+
+    Ok(())
+}
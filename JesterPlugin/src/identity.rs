@@ -0,0 +1,77 @@
+use crate::errors::ISUProcessorError;
+use adler::adler32;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+// number of bytes at the start of a file that are checksummed to recognise it. This covers the LVM
+// header, which carries the time the DAS created the file
+pub const HEAD_BYTES: u64 = 1024;
+
+// FileIdentity tells apart a file we have been tailing from a new file written under the same
+// path, whether the DAS recreated it, truncated it or rotated it out for a new one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileIdentity {
+    pub inode: Option<u64>, // not available on all platforms
+    pub size: u64,
+    pub head_len: u64,
+    pub head_checksum: u32,
+}
+
+impl FileIdentity {
+    pub fn read(path: &Path) -> Result<FileIdentity, ISUProcessorError> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let head_len = metadata.len().min(HEAD_BYTES);
+
+        Ok(FileIdentity {
+            inode: inode(&metadata),
+            size: metadata.len(),
+            head_len,
+            head_checksum: adler32(BufReader::new(file.take(head_len)))?,
+        })
+    }
+
+    // changed compares the file currently at path against this identity, returning the reason the
+    // file should be treated as new or None if it's the same file
+    pub fn changed(&self, path: &Path) -> Result<Option<String>, ISUProcessorError> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+
+        if let (Some(old), Some(new)) = (self.inode, inode(&metadata)) {
+            if old != new {
+                return Ok(Some(format!("inode changed from {old} to {new}")));
+            }
+        }
+
+        if metadata.len() < self.size {
+            return Ok(Some(format!(
+                "file shrank from {} to {} bytes",
+                self.size,
+                metadata.len()
+            )));
+        }
+
+        // the file is at least as long as before so the head we checksummed is all still there
+        let checksum = adler32(BufReader::new(file.take(self.head_len)))?;
+        if checksum != self.head_checksum {
+            return Ok(Some(format!(
+                "first {} bytes changed, checksum {} is now {checksum}",
+                self.head_len, self.head_checksum
+            )));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
@@ -0,0 +1,264 @@
+use crate::config::{self, CleanupPolicy};
+use crate::errors::ISUProcessorError;
+use jester_core::DataSourceMessage;
+use rusqlite::Connection;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::UnboundedSender;
+
+// outputs are written under this extension and only renamed to their final name once the state
+// update that produced them has been committed
+pub const PART_EXTENSION: &str = "part";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Timeseries,
+    Graph,
+    // rows that failed validation, kept in the output directory rather than uploaded
+    Quarantine,
+    // provenance of a timeseries output, sent on the graph channel as an update of the output's node
+    Manifest,
+}
+
+impl Channel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Timeseries => "timeseries",
+            Channel::Graph => "graph",
+            Channel::Quarantine => "quarantine",
+            Channel::Manifest => "manifest",
+        }
+    }
+
+    fn from_str(channel: &str) -> Channel {
+        match channel {
+            "graph" => Channel::Graph,
+            "quarantine" => Channel::Quarantine,
+            "manifest" => Channel::Manifest,
+            _ => Channel::Timeseries,
+        }
+    }
+}
+
+// Output is a generated file on its way to Jester, path is its final name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub path: PathBuf,
+    pub channel: Channel,
+}
+
+// Destination is where outputs are delivered to and what becomes of them afterwards
+pub struct Destination<'a> {
+    pub timeseries: &'a UnboundedSender<DataSourceMessage>,
+    pub graph: Option<&'a UnboundedSender<DataSourceMessage>>,
+    pub cleanup: CleanupPolicy,
+}
+
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(format!(".{PART_EXTENSION}"));
+    PathBuf::from(part)
+}
+
+// create_output creates the part file for an output, the output itself only appears on publish
+pub fn create_output(path: &Path) -> Result<File, ISUProcessorError> {
+    Ok(File::create(part_path(path))?)
+}
+
+// record adds the outputs to the journal. It must run in the same transaction as the state update
+// for the rows in those outputs, so that either both or neither survive a crash
+pub fn record(db: &Connection, source: &str, outputs: &[Output]) -> Result<(), ISUProcessorError> {
+    let mut stmt = db.prepare(
+        "INSERT INTO isu_pending_output(path, source, channel, sent) VALUES (?1,?2,?3,0)",
+    )?;
+    for output in outputs {
+        stmt.execute([
+            output.path.to_string_lossy().as_ref(),
+            source,
+            output.channel.as_str(),
+        ])?;
+    }
+
+    Ok(())
+}
+
+// deliver publishes committed outputs and hands them to Jester. Outputs Jester doesn't delete can't
+// be told apart once uploaded, so they leave the journal as soon as they are sent
+pub fn deliver(
+    db: &Connection,
+    outputs: &[Output],
+    destination: &Destination,
+) -> Result<(), ISUProcessorError> {
+    for output in outputs {
+        publish(&output.path)?;
+        if output.channel == Channel::Quarantine {
+            db.execute(
+                "DELETE FROM isu_pending_output WHERE path = ?1",
+                [output.path.to_string_lossy().as_ref()],
+            )?;
+            continue;
+        }
+
+        send(output, destination)?;
+        let sql = if destination.cleanup.delete_uploaded() {
+            "UPDATE isu_pending_output SET sent = 1 WHERE path = ?1"
+        } else {
+            "DELETE FROM isu_pending_output WHERE path = ?1"
+        };
+        db.execute(sql, [output.path.to_string_lossy().as_ref()])?;
+    }
+
+    Ok(())
+}
+
+// recover finishes delivering journaled outputs. Outputs that were committed but never sent are
+// published and sent. Jester removes files once they are uploaded, so a sent output whose file is
+// gone is done and dropped from the journal. One that still exists after a restart was lost with
+// the old process's channel, resend_sent sends it again
+pub fn recover(
+    db: &Connection,
+    resend_sent: bool,
+    destination: &Destination,
+) -> Result<(), ISUProcessorError> {
+    let pending = {
+        let mut stmt = db.prepare("SELECT path, channel, sent FROM isu_pending_output")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                Output {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    channel: Channel::from_str(row.get::<_, String>(1)?.as_str()),
+                },
+                row.get::<_, bool>(2)?,
+            ))
+        })?;
+        rows.collect::<Result<Vec<(Output, bool)>, rusqlite::Error>>()?
+    };
+
+    for (output, sent) in pending {
+        let exists = output.path.exists() || part_path(&output.path).exists();
+        match (sent, exists) {
+            (false, true) => {
+                log::info!("delivering {:?} left over from an earlier run", output.path);
+                deliver(db, &[output], destination)?;
+            }
+            (true, true) => {
+                if resend_sent {
+                    log::info!(
+                        "resending {:?}, it wasn't uploaded before the restart",
+                        output.path
+                    );
+                    send(&output, destination)?;
+                }
+            }
+            (sent, false) => {
+                if !sent {
+                    log::warn!("output {:?} was removed before it was sent", output.path);
+                }
+
+                db.execute(
+                    "DELETE FROM isu_pending_output WHERE path = ?1",
+                    [output.path.to_string_lossy().as_ref()],
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// remove_orphans deletes part files in the output directory that the journal doesn't know about.
+// These were being written when the plugin stopped and their rows will be read again
+pub fn remove_orphans(db: &Connection, dir: &Path) -> Result<(), ISUProcessorError> {
+    for entry in fs::read_dir(dir)? {
+        let part = entry?.path();
+        if part.extension().and_then(|e| e.to_str()) != Some(PART_EXTENSION) {
+            continue;
+        }
+
+        // only touch files we could have written, outputs are named after a uuid
+        let output = part.with_extension("");
+        if !is_output(&output) {
+            continue;
+        }
+
+        let journaled: bool = db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM isu_pending_output WHERE path = ?1)",
+            [output.to_string_lossy().as_ref()],
+            |row| row.get(0),
+        )?;
+        if !journaled {
+            log::warn!("removing orphaned output {part:?}");
+            fs::remove_file(part)?;
+        }
+    }
+
+    Ok(())
+}
+
+// remove_expired deletes published outputs in the output directory older than the retention period.
+// Outputs still waiting in the journal are kept however old they are
+pub fn remove_expired(db: &Connection, dir: &Path, days: u64) -> Result<(), ISUProcessorError> {
+    let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let output = entry.path();
+        let extension = output.extension().and_then(|e| e.to_str());
+        if extension == Some(PART_EXTENSION) || !is_output(&output) {
+            continue;
+        }
+
+        if entry.metadata()?.modified()? >= cutoff {
+            continue;
+        }
+
+        let journaled: bool = db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM isu_pending_output WHERE path = ?1)",
+            [output.to_string_lossy().as_ref()],
+            |row| row.get(0),
+        )?;
+        if !journaled {
+            log::debug!("removing expired output {output:?}");
+            fs::remove_file(output)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_output(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|f| f.to_str())
+        .is_some_and(config::contains_uuid)
+}
+
+fn publish(path: &Path) -> Result<(), ISUProcessorError> {
+    let part = part_path(path);
+    if part.exists() {
+        fs::rename(part, path)?;
+    }
+
+    Ok(())
+}
+
+fn send(output: &Output, destination: &Destination) -> Result<(), ISUProcessorError> {
+    let chan = match (output.channel, destination.graph) {
+        (Channel::Timeseries, _) => destination.timeseries,
+        (Channel::Graph | Channel::Manifest, Some(c)) => c,
+        // Jester was restarted without a graph channel, there's nowhere to send graph records and
+        // nothing else would remove them
+        (Channel::Graph | Channel::Manifest, None) => {
+            log::warn!("no graph channel to send {:?} on, removing it", output.path);
+            if output.path.exists() {
+                fs::remove_file(&output.path)?;
+            }
+            return Ok(());
+        }
+        (Channel::Quarantine, _) => return Ok(()),
+    };
+
+    let delete = destination.cleanup.delete_uploaded();
+    chan.send(DataSourceMessage::File((output.path.clone(), delete)))
+        .map_err(|_| ISUProcessorError::ChannelSendError)
+}
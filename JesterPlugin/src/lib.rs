@@ -1,23 +1,81 @@
 #![feature(closure_track_caller)]
-mod errors;
+pub mod admin;
+mod aggregate;
+pub mod backfill;
+mod channels;
+pub mod classify;
+pub mod config;
+pub mod errors;
+mod events;
+mod format;
+mod graph;
+mod health;
+mod identity;
+mod journal;
+mod lvm;
+mod manifest;
+mod migrations;
+mod runs;
+mod state;
+mod tail;
 mod tests;
+mod timestamps;
+mod validate;
 
+use crate::aggregate::{Aggregation, Aggregator, Window};
+use crate::channels::ChannelMap;
+use crate::classify::{FileKind, Rules};
+use crate::config::{CleanupPolicy, Configuration};
 use crate::errors::ISUProcessorError;
-use chrono::NaiveDateTime;
+use crate::events::{Event, EVENT_HEADERS, EVENT_TYPES};
+use crate::format::{ChunkLimits, ColumnType, OutputFormat, RowWriter};
+use crate::identity::FileIdentity;
+use crate::journal::{Channel, Destination, Output};
+use crate::lvm::{LvmHeader, COMMENT_COLUMN};
+use crate::manifest::{Manifest, Parser, Source};
+use crate::runs::{RunColumns, RunStats, RUN_ID_COLUMN};
+use crate::state::StateStore;
+use crate::timestamps::{DATETIME_COLUMN, TIMESTAMP_COLUMN, X_VALUE_COLUMN};
+use crate::validate::{Validator, REASON_COLUMN};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use jester_core::errors::ProcessorError;
 use jester_core::DataSourceMessage;
 use sqlx::sqlite::SqliteRow;
 use sqlx::{Error, Pool, Row, Sqlite};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, Take};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::time::{Duration, Instant};
 use rusqlite::Connection;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc::UnboundedSender;
-use uuid::Uuid;
+
+// how often expired outputs are looked for when they are being retained
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// how often runs are checked for the idle timeout between process calls
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct ISUProcessor {
-    conn: rusqlite::Connection
+    state: StateStore,
+    config: Configuration,
+    timezone: Tz, // timezone of the DAS clock, run directory times are local to it
+    rules: Rules,
+    validator: Validator,
+    channels: ChannelMap,
+    recovered: AtomicBool, // whether the journal has been recovered since the plugin was loaded
+    last_cleanup: Mutex<Option<Instant>>,
+    ready: AtomicBool, // set once init has passed the health check
+    senders: Mutex<Option<Senders>>, // channels of the last process call, idle runs are sent on them
+}
+
+// Senders are the channels Jester handed to a process call
+#[derive(Clone)]
+struct Senders {
+    timeseries: UnboundedSender<DataSourceMessage>,
+    graph: Option<UnboundedSender<DataSourceMessage>>,
 }
 pub struct ISUFile {
     path: String,
@@ -25,18 +83,55 @@ pub struct ISUFile {
     last_index: i32,
     headers: String,
     time: String, // time string as pulled from the file directory
+    lvm_header: Option<LvmHeader>, // only engineering data files carry an LVM header
+    identity: Option<FileIdentity>, // files tracked before identities were stored have none
+    accepted_rows: i64,
+    rejected_rows: i64, // rows written to quarantine instead of the output
+    window: Option<Window>, // the aggregation window left open by the last pass
+    sequence: i64, // the sequence number of the file's next output
 }
 
 impl ISUProcessor {
     fn new() -> Result<ISUProcessor, ISUProcessorError> {
-        let conn = Connection::open("./agn201_plugin")?;
-        Ok(ISUProcessor {conn})
+        ISUProcessor::with_config(Configuration::load()?)
+    }
+
+    fn with_config(config: Configuration) -> Result<ISUProcessor, ISUProcessorError> {
+        std::fs::create_dir_all(&config.output_dir)?;
+        let state = StateStore::open(&config.state_db)?;
+        let timezone = timestamps::das_timezone(config.das_timezone.as_deref())?;
+        let rules = Rules::load(config.file_rules.as_deref())?;
+        let validator = Validator::load(config.validation_rules.as_deref())?;
+        let channels = ChannelMap::load(config.channel_map.as_deref())?;
+
+        Ok(ISUProcessor {
+            state,
+            config,
+            timezone,
+            rules,
+            validator,
+            channels,
+            recovered: AtomicBool::new(false),
+            last_cleanup: Mutex::new(None),
+            ready: AtomicBool::new(false),
+            senders: Mutex::new(None),
+        })
+    }
+
+    // health_check verifies the state database is writable and its schema is current
+    pub fn health_check(&self) -> Result<(), ISUProcessorError> {
+        health::check(&*self.state.lock()?)
     }
 }
 impl jester_core::Processor for ISUProcessor {
     fn init(&self, db: Pool<Sqlite>) -> Result<(), ProcessorError> {
-
-        self.conn.execute("CREATE TABLE IF NOT EXISTS isu (path text UNIQUE ON CONFLICT REPLACE, last_position_read integer, headers text, time text, last_index integer);", []).map_err(|e| ProcessorError::from(ISUProcessorError::Rusqlite(e)))?;
+        create_tables(&*self.state.lock()?)?;
+        self.health_check()?;
+        journal::remove_orphans(&*self.state.lock()?, &self.config.output_dir)?;
+        self.remove_expired()?;
+        self.ready.store(true, Ordering::SeqCst);
+        // runs that went idle while the plugin was stopped
+        self.close_idle_runs()?;
         Ok(())
 
     /*    // in order to use the Tokio runtime to do blocking operations on async functions we must
@@ -68,268 +163,1104 @@ impl jester_core::Processor for ISUProcessor {
         timeseries_chan: Option<UnboundedSender<DataSourceMessage>>,
         graph_chan: Option<UnboundedSender<DataSourceMessage>>,
     ) -> Result<(), ProcessorError> {
-        let path = match file.to_str() {
-            None => return Err(ProcessorError::from(ISUProcessorError::BlankPath)),
-            Some(p) => p,
-        };
+        if !self.ready.load(Ordering::SeqCst) {
+            return Err(ProcessorError::from(ISUProcessorError::NotReady));
+        }
 
-        if path.contains("Events") {
-            let result = match fetch_file(path, &self.conn)? {
-                None => initial_event_process(file, &self.conn)?,
-                // on some we're basically tailing the file so run the tail function
-                Some(f) => tail_event_process(f, file, &self.conn)?,
-            };
+        Ok(self.process_file(file, timeseries_chan, graph_chan)?)
+    }
+}
 
-            timeseries_chan
-                .ok_or(ISUProcessorError::NoChannelError)?
-                .send(DataSourceMessage::File((result, true)))?;
-        } else {
-            let result = match fetch_file(path, &self.conn)? {
-                None => initial_process(file, &self.conn)?,
-                // on some we're basically tailing the file so run the tail function
-                Some(f) => tail_process(f, file, &self.conn)?,
-            };
+// SharedProcessor is the processor registered with Jester. It shares the ISU processor with the
+// thread that closes idle runs, which is started on init and stops once the processor is dropped
+struct SharedProcessor {
+    processor: Arc<ISUProcessor>,
+    watching: Once,
+}
 
-            timeseries_chan
-                .ok_or(ISUProcessorError::NoChannelError)?
-                .send(DataSourceMessage::File((result, true)))?;
-        }
+impl jester_core::Processor for SharedProcessor {
+    fn init(&self, db: Pool<Sqlite>) -> Result<(), ProcessorError> {
+        self.processor.init(db)?;
 
+        let processor = Arc::downgrade(&self.processor);
+        self.watching.call_once(|| watch_idle_runs(processor));
         Ok(())
     }
+
+    fn process(
+        &self,
+        file: PathBuf,
+        db: Pool<Sqlite>,
+        timeseries_chan: Option<UnboundedSender<DataSourceMessage>>,
+        graph_chan: Option<UnboundedSender<DataSourceMessage>>,
+    ) -> Result<(), ProcessorError> {
+        self.processor.process(file, db, timeseries_chan, graph_chan)
+    }
 }
 
-fn initial_process(path: PathBuf, db: &Connection) -> Result<PathBuf, ISUProcessorError> {
-    let uuid = Uuid::new_v4();
-    let file = File::open(&path)?;
-    let output_file = File::create(format!("{uuid}.csv"))?;
-    let mut reader = BufReader::new(file);
-    let mut writer = csv::WriterBuilder::new()
-        .flexible(true)
-        .from_writer(output_file);
+// watch_idle_runs closes idle runs every IDLE_CHECK_INTERVAL until the processor is dropped
+fn watch_idle_runs(processor: Weak<ISUProcessor>) {
+    let watcher = std::thread::Builder::new()
+        .name(String::from("isu-idle-runs"))
+        .spawn(move || loop {
+            std::thread::sleep(IDLE_CHECK_INTERVAL);
+            let Some(processor) = processor.upgrade() else {
+                return;
+            };
+            if let Err(e) = processor.close_idle_runs() {
+                log::error!("unable to close idle runs: {e}");
+            }
+        });
 
-    let mut header_count = 0;
-    loop {
-        let mut s = String::new();
-        reader.read_line(&mut s)?;
+    if let Err(e) = watcher {
+        log::error!("unable to start watching for idle runs, they're closed on the next process call: {e}");
+    }
+}
 
-        if s.contains("***End_of_Header***") {
-            header_count += 1
+// UnavailableProcessor is registered in place of the ISU processor when it can't be constructed,
+// so that Jester gets the reason as an error instead of the host process aborting
+struct UnavailableProcessor {
+    reason: String,
+}
+
+impl jester_core::Processor for UnavailableProcessor {
+    fn init(&self, _db: Pool<Sqlite>) -> Result<(), ProcessorError> {
+        Err(ProcessorError::from(ISUProcessorError::Unavailable(self.reason.clone())))
+    }
+
+    fn process(
+        &self,
+        _file: PathBuf,
+        _db: Pool<Sqlite>,
+        _timeseries_chan: Option<UnboundedSender<DataSourceMessage>>,
+        _graph_chan: Option<UnboundedSender<DataSourceMessage>>,
+    ) -> Result<(), ProcessorError> {
+        Err(ProcessorError::from(ISUProcessorError::Unavailable(self.reason.clone())))
+    }
+}
+
+impl ISUProcessor {
+    fn process_file(
+        &self,
+        file: PathBuf,
+        timeseries_chan: Option<UnboundedSender<DataSourceMessage>>,
+        graph_chan: Option<UnboundedSender<DataSourceMessage>>,
+    ) -> Result<(), ISUProcessorError> {
+        let path = file.to_str().ok_or(ISUProcessorError::BlankPath)?;
+
+        let kind = self.rules.classify(&file)?;
+        if kind == FileKind::Ignore {
+            log::debug!("ignoring {path}");
+            return Ok(());
         }
 
-        // total of two headers in this file
-        if header_count >= 2 {
-            break;
+        // a pass over the file started by another call has to end before this one reads it
+        let file_lock = self.state.file_lock(&file)?;
+        let _pass = file_lock.lock().map_err(|_| ISUProcessorError::ThreadError)?;
+        if is_finished(path, &*self.state.lock()?)? {
+            log::debug!("{path} is marked finished, not tailing it");
+            return Ok(());
         }
+
+        let timeseries_chan = timeseries_chan.ok_or(ISUProcessorError::NoChannelError)?;
+        *self.senders.lock().map_err(|_| ISUProcessorError::ThreadError)? = Some(Senders {
+            timeseries: timeseries_chan.clone(),
+            graph: graph_chan.clone(),
+        });
+        let destination = Destination {
+            timeseries: &timeseries_chan,
+            graph: graph_chan.as_ref(),
+            cleanup: self.config.cleanup,
+        };
+        // hand off anything an earlier call left undelivered before producing more
+        let (current, sequence) = {
+            let db = self.state.lock()?;
+            let resend_sent = !self.recovered.swap(true, Ordering::SeqCst);
+            journal::recover(&db, resend_sent, &destination)?;
+            // a file that replaced another carries on its numbering, so no output name is used twice
+            let sequence = fetch_file(path, &db)?.map_or(0, |f| f.sequence);
+            (fetch_current(&file, &db)?, sequence)
+        };
+
+        let run_start = timestamps::run_start(run_directory(&file)?.as_str(), self.timezone)?;
+        let format = self.config.format(kind);
+        let new_output = |sequence| self.config.output_path(kind, run_start, &file, sequence, format.extension());
+
+        let options = OutputOptions {
+            validator: &self.validator,
+            channels: &self.channels,
+            aggregation: self.config.aggregation.get(&kind),
+            format,
+            limits: self.config.chunk_limits(),
+            stop_index: None,
+            manifests: graph_chan.is_some(),
+        };
+        // the file is read without holding the state database, other files are processed meanwhile
+        let pass = match (kind, current) {
+            (FileKind::Events, None) => {
+                initial_event_process(file.clone(), &new_output, &options, sequence, self.timezone)?
+            }
+            // on some we're basically tailing the file so run the tail function
+            (FileKind::Events, Some(f)) => {
+                tail_event_process(f, file.clone(), &new_output, &options, self.timezone)?
+            }
+            (_, None) => initial_process(file.clone(), &new_output, &options, sequence, self.timezone)?,
+            (_, Some(f)) => tail_process(f, file.clone(), &new_output, &options, self.timezone)?,
+        };
+
+        // the file state, the outputs holding the rows read and their journal entries are committed
+        // together. A crash before the commit leaves only part files, which are removed on init. The
+        // outputs are delivered before the database is let go so another call's recovery can't
+        // send them as well
+        let db = self.state.lock()?;
+        let tx = db.unchecked_transaction()?;
+        let results = save_pass(&file, pass, &tx)?;
+        let mut outputs = self.with_graphs(&file, results, graph_chan.is_some(), &tx)?;
+        // a file of a newer run is how the end of the last one is noticed
+        let idle_timeout = self.config.run_idle_timeout();
+        outputs.extend(self.close_runs(idle_timeout, graph_chan.is_some(), Some(&file), &tx)?);
+
+        journal::record(&tx, path, &outputs)?;
+        tx.commit()?;
+
+        journal::deliver(&db, &outputs, &destination)?;
+        drop(db);
+        self.remove_expired()
     }
 
-    let parent = &path
-        .parent()
-        .ok_or(ISUProcessorError::BlankPath)?
-        .components()
-        .last()
-        .ok_or(ISUProcessorError::BlankPath)?;
+    // with_graphs adds the graph outputs of a file's timeseries outputs
+    fn with_graphs(
+        &self,
+        file: &Path,
+        results: Vec<Output>,
+        graph: bool,
+        db: &Connection,
+    ) -> Result<Vec<Output>, ISUProcessorError> {
+        let mut outputs = vec![];
+        for result in results {
+            // the graph channel is optional, if Jester wasn't configured with one we only send timeseries.
+            // Quarantined rows aren't ingested so they have no graph
+            if result.channel == Channel::Timeseries && graph {
+                let graph = write_graph(file, &result.path, &self.channels, db)?;
+                outputs.push(result);
+                outputs.push(Output { path: graph, channel: Channel::Graph });
+            } else {
+                outputs.push(result);
+            }
+        }
 
-    let time = NaiveDateTime::parse_from_str(
-        parent
-            .as_os_str()
-            .to_str()
-            .ok_or(ISUProcessorError::BlankPath)?,
-        "%b_%d_%Y_%H_%M",
-    )?;
+        Ok(outputs)
+    }
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .flexible(true)
-        .from_reader(reader);
+    // close_runs closes the runs that have ended. The aggregation windows their files left open are
+    // written out and, if there's a graph channel, a summary of each run is sent as an update of its
+    // graph node. A run with a file
+    // another call is reading is left for a later call to close, once that pass is saved. The caller
+    // holds the lock of the locked file already
+    fn close_runs(
+        &self,
+        idle_timeout: Option<chrono::Duration>,
+        graph: bool,
+        locked: Option<&Path>,
+        db: &Connection,
+    ) -> Result<Vec<Output>, ISUProcessorError> {
+        let now = Utc::now();
+        let mut outputs = vec![];
+        for run in runs::ended(db, now, idle_timeout, self.timezone)? {
+            let files = tracked_files(&run.directory, db)?;
+            let locks = files
+                .iter()
+                .filter(|f| Some(f.as_path()) != locked)
+                .map(|f| self.state.file_lock(f))
+                .collect::<Result<Vec<_>, ISUProcessorError>>()?;
+            // waiting for a pass while holding the database would deadlock, it needs the database to
+            // save
+            let passes: Vec<_> = locks.iter().filter_map(|l| l.try_lock().ok()).collect();
+            if passes.len() < locks.len() {
+                log::debug!("a file of run {} is being read, closing the run later", run.name());
+                continue;
+            }
 
-    let mut headers = csv_reader.headers()?.clone();
-    headers.truncate(headers.len() - 1);
-    headers.push_field("DateTime");
-    writer.write_byte_record(headers.as_byte_record())?;
+            runs::close(db, &run, now)?;
+            log::info!("run {} has ended", run.name());
+            for file in files {
+                let results = self.flush_window(&file, graph, db)?;
+                outputs.extend(self.with_graphs(&file, results, graph, db)?);
+            }
 
-    for result in csv_reader.records() {
-        let mut record = result?;
+            // the summary is an update of the run's graph node, without a graph channel it has
+            // nowhere to go
+            if graph {
+                let summary = self.config.summary_path(run.run_start, &run.directory);
+                graph::write_records(&graph::summary_records(&run, now), &summary)?;
+                outputs.push(Output { path: summary, channel: Channel::Graph });
+            }
+        }
 
-        record.push_field(format!("{time}").as_str());
-        writer.write_byte_record(record.as_byte_record())?;
+        Ok(outputs)
     }
 
-    let path = match path.into_os_string().into_string() {
-        Ok(s) => s,
-        Err(_) => return Err(ISUProcessorError::BlankPath),
-    };
+    // flush_window writes the aggregation window the file's last pass left open
+    fn flush_window(&self, file: &Path, graph: bool, db: &Connection) -> Result<Vec<Output>, ISUProcessorError> {
+        let mut db_file = match fetch_file(file.to_str().ok_or(ISUProcessorError::BlankPath)?, db)? {
+            Some(f) if f.window.is_some() => f,
+            _ => return Ok(vec![]),
+        };
+        let kind = self.rules.classify(file)?;
+        let run_start = timestamps::parse_stored_time(db_file.time.as_str(), self.timezone)?;
+        let format = self.config.format(kind);
+        let new_output = |sequence| self.config.output_path(kind, run_start, file, sequence, format.extension());
+        let options = OutputOptions {
+            validator: &self.validator,
+            channels: &self.channels,
+            aggregation: self.config.aggregation.get(&kind),
+            format,
+            limits: self.config.chunk_limits(),
+            stop_index: None,
+            manifests: graph,
+        };
 
-    let headers: Vec<String> = headers.deserialize(None)?;
+        let headers: Vec<String> = db_file.headers.split(',').map(String::from).collect();
+        let run_id = run_directory(file)?;
+        let position = db_file.last_position_read.try_into()?;
+        let source = Source { path: file, run_id: &run_id, parser: Parser::Lvm, start: position };
+        let mut stream = OutputStream::new(&new_output, &options, source, run_start, db_file.window.take(), db_file.sequence);
+        stream.flush(&headers)?;
+        let (outputs, _, sequence) = stream.finish(position)?;
+        db_file.sequence = sequence;
+        save_file(db_file, db)?;
 
-    writer.flush()?;
-    save_file(
-        ISUFile {
-            path,
-            last_position_read: csv_reader.into_inner().stream_position()?.try_into()?,
-            last_index: 0,
-            headers: headers.join(","),
-            time: format!("{time}"),
-        },
-        db,
-    )?;
+        Ok(outputs)
+    }
 
-    Ok(PathBuf::from(format!("{uuid}.csv")))
+    // end_runs closes every open run, for when no more files of them will be processed
+    fn end_runs(
+        &self,
+        timeseries_chan: UnboundedSender<DataSourceMessage>,
+        graph_chan: Option<UnboundedSender<DataSourceMessage>>,
+    ) -> Result<(), ISUProcessorError> {
+        let senders = Senders { timeseries: timeseries_chan, graph: graph_chan };
+        self.send_ended_runs(Some(chrono::Duration::zero()), Some(&senders))
+    }
+
+    // close_idle_runs closes the runs past the idle timeout without a file being processed, the DAS
+    // may go quiet without a later run ever starting. Their summaries are sent on the channels of the
+    // last process call, before the first call they're left in the journal for it to deliver
+    fn close_idle_runs(&self) -> Result<(), ISUProcessorError> {
+        let senders = self.senders.lock().map_err(|_| ISUProcessorError::ThreadError)?.clone();
+        self.send_ended_runs(self.config.run_idle_timeout(), senders.as_ref())
+    }
+
+    // send_ended_runs closes the runs that have ended and delivers their outputs if there are
+    // channels to send them on
+    fn send_ended_runs(
+        &self,
+        idle_timeout: Option<chrono::Duration>,
+        senders: Option<&Senders>,
+    ) -> Result<(), ISUProcessorError> {
+        let db = self.state.lock()?;
+        let tx = db.unchecked_transaction()?;
+        let graph = senders.is_none_or(|s| s.graph.is_some());
+        let outputs = self.close_runs(idle_timeout, graph, None, &tx)?;
+        journal::record(&tx, "", &outputs)?;
+        tx.commit()?;
+
+        match senders {
+            Some(senders) => {
+                let destination = Destination {
+                    timeseries: &senders.timeseries,
+                    graph: senders.graph.as_ref(),
+                    cleanup: self.config.cleanup,
+                };
+                journal::deliver(&db, &outputs, &destination)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // remove_expired removes outputs past their retention, at most once every CLEANUP_INTERVAL
+    fn remove_expired(&self) -> Result<(), ISUProcessorError> {
+        if self.config.cleanup != CleanupPolicy::Retain {
+            return Ok(());
+        }
+
+        let mut last_cleanup = self.last_cleanup.lock().map_err(|_| ISUProcessorError::ThreadError)?;
+        if last_cleanup.is_some_and(|t| t.elapsed() < CLEANUP_INTERVAL) {
+            return Ok(());
+        }
+
+        journal::remove_expired(&*self.state.lock()?, &self.config.output_dir, self.config.retention_days)?;
+        *last_cleanup = Some(Instant::now());
+        Ok(())
+    }
+}
+
+// create_tables brings the state database schema up to date, see migrations for what it holds
+fn create_tables(db: &Connection) -> Result<(), ISUProcessorError> {
+    migrations::migrate(db)
+}
+
+// write_graph writes the run, channel and file nodes for a processed file, returning the path of
+// the graph output
+fn write_graph(
+    path: &Path,
+    output: &Path,
+    channels: &ChannelMap,
+    db: &Connection,
+) -> Result<PathBuf, ISUProcessorError> {
+    let db_file = fetch_file(path.to_str().ok_or(ISUProcessorError::BlankPath)?, db)?
+        .ok_or(ISUProcessorError::BlankPath)?;
+    let records = graph::build_records(run_directory(path)?.as_str(), &db_file, output, channels);
+    let graph_file = graph::graph_path(output)?;
+    graph::write_records(&records, &graph_file)?;
+
+    Ok(graph_file)
 }
 
-fn initial_event_process(path: PathBuf, db: &Connection) -> Result<PathBuf, ISUProcessorError> {
-    let uuid = Uuid::new_v4();
-    let file = File::open(&path)?;
-    let output_file = File::create(format!("{uuid}.csv"))?;
-    let mut reader = BufReader::new(file);
-    let mut writer = csv::WriterBuilder::new()
-        .flexible(true)
-        .from_writer(output_file);
+// Pass is what a pass over a file read. Nothing is stored until the pass is saved, so files are
+// read without holding the state database
+struct Pass {
+    file: Option<ISUFile>, // None until the file's header is complete, the file is then read again
+    run_start: DateTime<Utc>,
+    stats: RunStats,
+    outputs: Vec<Output>,
+}
+
+// save_pass stores the state the pass left the file in and adds what it read to the stats of its
+// run, returning the outputs of the pass
+fn save_pass(path: &Path, pass: Pass, db: &Connection) -> Result<Vec<Output>, ISUProcessorError> {
+    let Some(file) = pass.file else {
+        log::debug!("header of {path:?} isn't complete yet, reading it again on the next pass");
+        return Ok(pass.outputs);
+    };
+
+    let directory = path.parent().ok_or(ISUProcessorError::BlankPath)?;
+    runs::record(db, directory, pass.run_start, &pass.stats, Utc::now())?;
+    save_file(file, db)?;
+    Ok(pass.outputs)
+}
 
-    let parent = &path
+// run_directory returns the name of the DAS run folder the file was written to
+fn run_directory(path: &Path) -> Result<String, ISUProcessorError> {
+    let parent = path
         .parent()
         .ok_or(ISUProcessorError::BlankPath)?
-        .components()
-        .last()
+        .file_name()
         .ok_or(ISUProcessorError::BlankPath)?;
 
-    let time = NaiveDateTime::parse_from_str(
-        parent
-            .as_os_str()
-            .to_str()
-            .ok_or(ISUProcessorError::BlankPath)?,
-        "%b_%d_%Y_%H_%M",
-    )?;
+    Ok(parent
+        .to_str()
+        .ok_or(ISUProcessorError::BlankPath)?
+        .to_string())
+}
 
-    writer.write_record(["Event", "Index", "DateTime"])?;
+// OutputOptions are the settings applied to the rows of engineering outputs
+struct OutputOptions<'a> {
+    validator: &'a Validator,
+    channels: &'a ChannelMap,
+    aggregation: Option<&'a Aggregation>,
+    format: OutputFormat,
+    limits: ChunkLimits,
+    stop_index: Option<i64>, // rows from this index on are left for a later pass
+    manifests: bool,         // manifests go on the graph channel, without one they aren't written
+}
 
-    let mut s = String::new();
-    let mut i = 0;
-    while let Ok(u) = reader.read_line(&mut s) {
-        if u == 0 {
+impl OutputOptions<'_> {
+    fn stops_at(&self, index: i64) -> bool {
+        self.stop_index.is_some_and(|s| index >= s)
+    }
+}
+
+// OutputStream writes engineering rows to outputs, a new output is started whenever the column set
+// changes so rows are never written under another segment's headers. Rejected rows go to a
+// quarantine output alongside, which is only created once there is a row for it. Headers are
+// renamed by the channel map as they're written. When the file kind is aggregated the windows go
+// to an output of their own, and the rows themselves are only written if raw output is kept. Once
+// an output reaches the chunk limits the next row starts a new chunk, numbered by sequence. What
+// was written is gathered into stats for the run's summary. Every row written is tagged with the id
+// of its run in a last column, before the reason of rejected rows. Each timeseries output gets a
+// manifest of where its rows came from as it's finished
+struct OutputStream<'a> {
+    new_output: &'a dyn Fn(i64) -> PathBuf,
+    options: &'a OutputOptions<'a>,
+    source: Source<'a>,
+    aggregator: Option<Aggregator>,
+    headers: Vec<String>,
+    names: Vec<String>, // the output names and types of the headers
+    types: Vec<ColumnType>,
+    sequence: i64, // the sequence number of the next output
+    base: Option<PathBuf>, // the output path the current outputs are named after
+    current: Option<OpenOutput>,
+    quarantine: Option<(PathBuf, RowWriter)>,
+    aggregate: Option<OpenOutput>,
+    finished: Vec<Output>,
+    position: u64, // where in the source the next row starts
+    run_columns: RunColumns,
+    stats: RunStats,
+}
+
+// OpenOutput is a timeseries output being written, with the indexes of the DAS rows read into it and
+// where in the source it was started
+struct OpenOutput {
+    path: PathBuf,
+    writer: RowWriter,
+    schema_hash: String,
+    indexes: Option<(i64, i64)>,
+    start: u64,
+}
+
+impl OpenOutput {
+    fn create(
+        path: PathBuf,
+        format: OutputFormat,
+        names: &[String],
+        types: &[ColumnType],
+        start: u64,
+    ) -> Result<OpenOutput, ISUProcessorError> {
+        Ok(OpenOutput {
+            writer: RowWriter::create(&path, format, names, types)?,
+            path,
+            schema_hash: manifest::schema_hash(names, types),
+            indexes: None,
+            start,
+        })
+    }
+
+    fn read(&mut self, index: i64) {
+        self.indexes = Some(self.indexes.map_or((index, index), |(first, _)| (first, index)));
+    }
+}
+
+impl<'a> OutputStream<'a> {
+    fn new(
+        new_output: &'a dyn Fn(i64) -> PathBuf,
+        options: &'a OutputOptions<'a>,
+        source: Source<'a>,
+        run_start: DateTime<Utc>,
+        window: Option<Window>,
+        sequence: i64,
+    ) -> OutputStream<'a> {
+        OutputStream {
+            new_output,
+            options,
+            source,
+            aggregator: options.aggregation.map(|a| Aggregator::new(a, run_start, window)),
+            headers: vec![],
+            names: vec![],
+            types: vec![],
+            sequence,
+            base: None,
+            current: None,
+            quarantine: None,
+            aggregate: None,
+            finished: vec![],
+            position: source.start,
+            run_columns: RunColumns::default(),
+            stats: RunStats::default(),
+        }
+    }
+
+    // read_to moves on to the row starting at position in the source, an output cut before it ends
+    // there and the next one starts there
+    fn read_to(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    // raw is whether every row is written, rather than only the windows they're aggregated into
+    fn raw(&self) -> bool {
+        self.options.aggregation.is_none_or(|a| a.raw)
+    }
+
+    fn start(&mut self, headers: &[String]) -> Result<(), ISUProcessorError> {
+        // the open window was collected under the old headers, it's written before they change
+        if !self.headers.is_empty() && self.headers != headers {
+            if let Some(row) = self.aggregator.as_mut().and_then(|a| a.close()) {
+                self.write_window(&row)?;
+            }
+        }
+        if let Some(aggregator) = self.aggregator.as_mut() {
+            aggregator.set_columns(headers);
+        }
+        self.headers = headers.to_vec();
+        self.names = self.options.channels.output_headers(headers);
+        self.types = engineering_types(headers);
+        self.run_columns = RunColumns::new(&self.headers, &self.names, &self.types, self.options.channels);
+        self.names.push(self.options.channels.name(RUN_ID_COLUMN));
+        self.types.push(ColumnType::Text);
+        self.next_chunk()
+    }
+
+    // flush writes the window the last pass left open, for when no more rows will come for it
+    fn flush(&mut self, headers: &[String]) -> Result<(), ISUProcessorError> {
+        let row = match self.aggregator.as_mut() {
+            None => return Ok(()),
+            Some(aggregator) => {
+                aggregator.set_columns(headers);
+                aggregator.close()
+            }
+        };
+
+        if let Some(row) = row {
+            self.headers = headers.to_vec();
+            self.base = Some((self.new_output)(self.sequence));
+            self.sequence += 1;
+            self.write_window(&row)?;
+        }
+        Ok(())
+    }
+
+    // start_events starts an output of event rows, which have fixed columns and are never renamed.
+    // The run id column is named the same as in engineering outputs
+    fn start_events(&mut self) -> Result<(), ISUProcessorError> {
+        self.headers = EVENT_HEADERS.map(String::from).to_vec();
+        self.names = self.headers.clone();
+        self.names.push(self.options.channels.name(RUN_ID_COLUMN));
+        self.types = EVENT_TYPES.to_vec();
+        self.types.push(ColumnType::Text);
+        self.run_columns = RunColumns::default();
+        self.next_chunk()
+    }
+
+    fn next_chunk(&mut self) -> Result<(), ISUProcessorError> {
+        self.finish_current()?;
+
+        let output = (self.new_output)(self.sequence);
+        self.sequence += 1;
+        if self.raw() {
+            let current = OpenOutput::create(output.clone(), self.options.format, &self.names, &self.types, self.position)?;
+            self.current = Some(current);
+        }
+        self.base = Some(output);
+        Ok(())
+    }
+
+    // full is whether the output being written has reached the chunk limits
+    fn full(&self) -> bool {
+        [&self.current, &self.aggregate]
+            .iter()
+            .any(|o| o.as_ref().is_some_and(|o| self.options.limits.reached(&o.writer)))
+    }
+
+    fn write(
+        &mut self,
+        record: &csv::StringRecord,
+        index: i64,
+        time: Option<DateTime<Utc>>,
+    ) -> Result<(), ISUProcessorError> {
+        if self.full() {
+            self.next_chunk()?;
+        }
+
+        if self.raw() {
+            let tagged = self.tagged(record);
+            match self.current.as_mut() {
+                None => return Err(ISUProcessorError::Unknown),
+                Some(current) => {
+                    current.writer.write(&tagged)?;
+                    current.read(index);
+                }
+            }
+        }
+        self.stats.add(&self.run_columns, record, time);
+
+        // rows without a sample time can't be placed in a window
+        let closed = match (self.aggregator.as_mut(), time) {
+            (Some(aggregator), Some(time)) => aggregator.add(record, time),
+            _ => None,
+        };
+        if let Some(row) = closed {
+            self.write_window(&row)?;
+        }
+        if let Some(aggregate) = self.aggregate.as_mut() {
+            aggregate.read(index);
+        }
+
+        Ok(())
+    }
+
+    // tagged is the row with the run id added
+    fn tagged(&self, record: &csv::StringRecord) -> csv::StringRecord {
+        let mut record = record.clone();
+        record.push_field(self.source.run_id);
+        record
+    }
+
+    fn write_window(&mut self, row: &csv::StringRecord) -> Result<(), ISUProcessorError> {
+        if self.full() {
+            self.next_chunk()?;
+        }
+
+        if self.aggregate.is_none() {
+            let (base, aggregator, aggregation) = match (&self.base, &self.aggregator, self.options.aggregation) {
+                (Some(base), Some(aggregator), Some(aggregation)) => (base, aggregator, aggregation),
+                _ => return Err(ISUProcessorError::Unknown),
+            };
+            let output = aggregate::aggregate_path(base, aggregation, self.options.format);
+
+            let mut headers = aggregator.headers(&self.headers, self.options.channels);
+            headers.push(self.options.channels.name(RUN_ID_COLUMN));
+            let mut types = aggregator.types();
+            types.push(ColumnType::Text);
+            self.aggregate = Some(OpenOutput::create(output, self.options.format, &headers, &types, self.position)?);
+        }
+
+        let row = self.tagged(row);
+        match self.aggregate.as_mut() {
+            None => Err(ISUProcessorError::Unknown),
+            Some(aggregate) => aggregate.writer.write(&row),
+        }
+    }
+
+    fn reject(&mut self, record: &csv::StringRecord, reason: &str) -> Result<(), ISUProcessorError> {
+        if self.quarantine.is_none() {
+            let output = match &self.base {
+                None => return Err(ISUProcessorError::Unknown),
+                Some(path) => validate::quarantine_path(path, self.options.format),
+            };
+
+            // rejected values are kept as they were read, so every column is text
+            let mut headers = self.names.clone();
+            headers.push(String::from(REASON_COLUMN));
+            let writer = RowWriter::create(&output, self.options.format, &headers, &[])?;
+            self.quarantine = Some((output, writer));
+        }
+
+        self.stats.rejected_rows += 1;
+        let mut record = self.tagged(record);
+        record.push_field(reason);
+        match self.quarantine.as_mut() {
+            None => Err(ISUProcessorError::Unknown),
+            Some((_, writer)) => writer.write(&record),
+        }
+    }
+
+    fn finish_current(&mut self) -> Result<(), ISUProcessorError> {
+        if let Some(current) = self.current.take() {
+            self.finish_output(current)?;
+        }
+
+        if let Some(aggregate) = self.aggregate.take() {
+            self.finish_output(aggregate)?;
+        }
+
+        if let Some((path, writer)) = self.quarantine.take() {
+            writer.finish()?;
+            self.finished.push(Output { path, channel: Channel::Quarantine });
+        }
+
+        Ok(())
+    }
+
+    // finish_output completes a timeseries output and writes its manifest if there's a graph channel
+    // to send it on. The output ends where the next row starts
+    fn finish_output(&mut self, output: OpenOutput) -> Result<(), ISUProcessorError> {
+        let rows = output.writer.rows();
+        output.writer.finish()?;
+        if !self.options.manifests {
+            self.finished.push(Output { path: output.path, channel: Channel::Timeseries });
+            return Ok(());
+        }
+
+        let manifest = Manifest {
+            output: output
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            source: self.source.path.to_path_buf(),
+            run_id: self.source.run_id.to_string(),
+            parser: self.source.parser,
+            byte_range: (output.start, self.position),
+            first_index: output.indexes.map(|(first, _)| first),
+            last_index: output.indexes.map(|(_, last)| last),
+            rows,
+            sha256: manifest::file_hash(&journal::part_path(&output.path))?,
+            schema_hash: output.schema_hash,
+            plugin_version: String::from(manifest::PLUGIN_VERSION),
+        };
+        let path = manifest::manifest_path(&output.path);
+        manifest::write(&manifest, &path)?;
+        self.finished.push(Output { path: output.path, channel: Channel::Timeseries });
+        self.finished.push(Output { path, channel: Channel::Manifest });
+        Ok(())
+    }
+
+    // finish closes the outputs, the pass read the file up to end. The outputs are returned along
+    // with the window left open and the sequence number of the next output
+    fn finish(mut self, end: u64) -> Result<(Vec<Output>, Option<Window>, i64), ISUProcessorError> {
+        self.read_to(end);
+        self.finish_current()?;
+
+        let window = self.aggregator.as_ref().and_then(|a| a.window());
+        Ok((self.finished, window, self.sequence))
+    }
+}
+
+// engineering_headers are the columns of an LVM column header row, less the empty column a closing
+// separator leaves. The run start goes in DateTime, Timestamp is the time of the sample itself
+fn engineering_headers(columns: &csv::StringRecord) -> Vec<String> {
+    let mut headers: Vec<String> = columns.iter().map(|c| c.trim().to_string()).collect();
+    while headers.last().is_some_and(|h| h.is_empty()) {
+        headers.pop();
+    }
+    headers.push(String::from(DATETIME_COLUMN));
+    headers.push(String::from(TIMESTAMP_COLUMN));
+    headers
+}
+
+// engineering_types are the types of engineering columns, every DAS channel is a reading
+fn engineering_types(headers: &[String]) -> Vec<ColumnType> {
+    headers
+        .iter()
+        .map(|h| match h.as_str() {
+            DATETIME_COLUMN | TIMESTAMP_COLUMN => ColumnType::Timestamp,
+            COMMENT_COLUMN => ColumnType::Text,
+            _ => ColumnType::Float,
+        })
+        .collect()
+}
+
+// write_engineering_rows writes the data rows left in the reader to the stream, following any new
+// segments the DAS starts. The stored headers and LVM header are updated as segments change. A
+// segment header that isn't completely written yet is left for the next call, the position to
+// continue from is returned
+fn write_engineering_rows(
+    db_file: &mut ISUFile,
+    reader: &mut BufReader<Take<File>>,
+    end: u64,
+    stream: &mut OutputStream,
+    run_start: DateTime<Utc>,
+) -> Result<u64, ISUProcessorError> {
+    let validator = stream.options.validator;
+    // files tracked before the LVM header was stored were all comma separated with dot decimals
+    let file_header = db_file.lvm_header.as_ref().map(|h| h.file.clone()).unwrap_or_default();
+    let mut headers: Vec<String> = db_file.headers.split(',').map(String::from).collect();
+    let mut x_column = headers.iter().position(|h| h == X_VALUE_COLUMN);
+    let mut checks = validator.columns(&headers);
+    let time = timestamps::format_time(Some(run_start));
+    let mut i: i64 = db_file.last_index.into();
+    let mut position = end;
+
+    loop {
+        let line_start = tail::position(reader, end);
+        stream.read_to(line_start);
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
             break;
         }
 
-        s.pop();
-        writer.write_record([
-            s[0..s.len() - 2].to_string(),
-            format!("{i}"),
-            format!("{time}"),
-        ])?;
-        s = String::new();
+        // LabVIEW separates segments with a line of nothing but separators
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim_matches(file_header.separator).trim().is_empty() {
+            continue;
+        }
+
+        if lvm::is_segment_start(line, &file_header) {
+            let (mut segment, columns) = match lvm::read_segment(line, reader, &file_header)? {
+                None => {
+                    position = line_start;
+                    break;
+                }
+                Some(s) => s,
+            };
+
+            segment.first_row = i;
+            let mut header = db_file.lvm_header.take().unwrap_or_default();
+            header.segment = segment;
+            db_file.lvm_header = Some(header);
+
+            let segment_headers = engineering_headers(&columns);
+            if segment_headers != headers {
+                log::info!(
+                    "columns of {} changed to {}, starting a new output",
+                    db_file.path,
+                    segment_headers.join(",")
+                );
+                headers = segment_headers;
+                x_column = headers.iter().position(|h| h == X_VALUE_COLUMN);
+                checks = validator.columns(&headers);
+                db_file.headers = headers.join(",");
+                stream.start(&headers)?;
+            }
+            continue;
+        }
+
+        if stream.options.stops_at(i) {
+            position = line_start;
+            break;
+        }
+
+        // data rows end in a separator for the empty Comment column, they're lined up with the
+        // headers before the plugin's columns are added
+        let mut record = file_header.record(line);
+        let columns = headers.len().saturating_sub(2);
+        if record.iter().skip(columns).any(|f| !f.trim().is_empty()) {
+            log::warn!("row {i} of {} has more fields than its {columns} columns, dropping the extra fields", db_file.path);
+        }
+        record.truncate(columns);
+        while record.len() < columns {
+            record.push_field("");
+        }
+        let first_row = db_file.lvm_header.as_ref().map_or(0, |h| h.segment.first_row);
+        let timestamp = timestamps::sample_time(
+            run_start,
+            x_column.and_then(|c| record.get(c)),
+            db_file.lvm_header.as_ref(),
+            i - first_row,
+        );
+
+        record.push_field(time.as_str());
+        record.push_field(timestamps::format_time(timestamp).as_str());
+        match checks.check(&record) {
+            None => {
+                stream.write(&record, i, timestamp)?;
+                db_file.accepted_rows += 1;
+            }
+            Some(reason) => {
+                log::debug!("quarantining row {i} of {}: {reason}", db_file.path);
+                stream.reject(&record, reason.as_str())?;
+                db_file.rejected_rows += 1;
+            }
+        }
         i += 1;
     }
 
+    db_file.last_index = i.try_into()?;
+    Ok(position)
+}
+
+fn initial_process(
+    path: PathBuf,
+    new_output: &dyn Fn(i64) -> PathBuf,
+    options: &OutputOptions,
+    sequence: i64, // of the first output
+    tz: Tz,
+) -> Result<Pass, ISUProcessorError> {
+    let (mut reader, end) = tail::open_complete(&path, 0)?;
+    let run_id = run_directory(&path)?;
+    let time = timestamps::run_start(run_id.as_str(), tz)?;
+
+    // the DAS writes the header in pieces as well, nothing is kept until the column row is written
+    let waiting = Pass { file: None, run_start: time, stats: RunStats::default(), outputs: vec![] };
+    let Some(lvm_header) = lvm::read_header(&mut reader)? else {
+        return Ok(waiting);
+    };
+    let mut columns = String::new();
+    if reader.read_line(&mut columns)? == 0 {
+        return Ok(waiting);
+    }
+    let headers = engineering_headers(&lvm_header.file.record(columns.as_str()));
+
+    let mut db_file = ISUFile {
+        path: path.to_str().ok_or(ISUProcessorError::BlankPath)?.to_string(),
+        last_position_read: 0,
+        last_index: 0,
+        headers: headers.join(","),
+        time: timestamps::format_time(Some(time)),
+        lvm_header: Some(lvm_header),
+        identity: None,
+        accepted_rows: 0,
+        rejected_rows: 0,
+        window: None,
+        sequence,
+    };
+
+    let source = Source { path: &path, run_id: &run_id, parser: Parser::Lvm, start: 0 };
+    let mut stream = OutputStream::new(new_output, options, source, time, None, sequence);
+    stream.start(&headers)?;
+    let position = write_engineering_rows(&mut db_file, &mut reader, end, &mut stream, time)?;
+
+    let stats = std::mem::take(&mut stream.stats);
+    let (outputs, window, sequence) = stream.finish(position)?;
+    db_file.window = window;
+    db_file.sequence = sequence;
+    db_file.last_position_read = position.try_into()?;
+    db_file.identity = Some(FileIdentity::read(&path)?);
+
+    Ok(Pass { file: Some(db_file), run_start: time, stats, outputs })
+}
+
+fn initial_event_process(
+    path: PathBuf,
+    new_output: &dyn Fn(i64) -> PathBuf,
+    options: &OutputOptions,
+    sequence: i64, // of the first output
+    tz: Tz,
+) -> Result<Pass, ISUProcessorError> {
+    let (mut reader, end) = tail::open_complete(&path, 0)?;
+    let run_id = run_directory(&path)?;
+    let time = timestamps::run_start(run_id.as_str(), tz)?;
+
+    let source = Source { path: &path, run_id: &run_id, parser: Parser::Events, start: 0 };
+    let mut stream = OutputStream::new(new_output, options, source, time, None, sequence);
+    stream.start_events()?;
+
+    let run_start = timestamps::format_time(Some(time));
+    let mut line = vec![];
+    let mut i = 0;
+    let mut position = end;
+    loop {
+        let line_start = tail::position(&reader, end);
+        stream.read_to(line_start);
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        // operator notes aren't always utf-8, they're kept with the invalid bytes replaced
+        let s = String::from_utf8_lossy(&line);
+
+        if let Some(event) = Event::parse(&s, time, tz) {
+            if options.stops_at(i.into()) {
+                position = line_start;
+                break;
+            }
+            stream.write(&csv::StringRecord::from(event.record(i, run_start.as_str()).to_vec()), i.into(), None)?;
+            i += 1;
+        }
+        line.clear();
+    }
+
+    let stats = std::mem::take(&mut stream.stats);
+    let (outputs, _, sequence) = stream.finish(position)?;
+    let identity = FileIdentity::read(&path)?;
     let path = match path.into_os_string().into_string() {
         Ok(s) => s,
         Err(_) => return Err(ISUProcessorError::BlankPath),
     };
 
-    writer.flush()?;
-    save_file(
-        ISUFile {
+    Ok(Pass {
+        file: Some(ISUFile {
             path,
-            last_position_read: reader.stream_position()?.try_into()?,
+            last_position_read: position.try_into()?,
             last_index: i,
-            headers: String::from("Event,Index,DateTime"),
-            time: format!("{time}"),
-        },
-        db,
-    )?;
-
-    Ok(PathBuf::from(format!("{uuid}.csv")))
+            headers: EVENT_HEADERS.join(","),
+            time: timestamps::format_time(Some(time)),
+            lvm_header: None,
+            identity: Some(identity),
+            accepted_rows: i.into(),
+            rejected_rows: 0,
+            window: None,
+            sequence,
+        }),
+        run_start: time,
+        stats,
+        outputs,
+    })
 }
 
 fn tail_process(
     mut db_file: ISUFile,
     path: PathBuf,
-    db: &Connection,
-) -> Result<PathBuf, ISUProcessorError> {
-    let uuid = Uuid::new_v4();
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    reader.seek(SeekFrom::Start(db_file.last_position_read.try_into()?))?;
-
-    let output_file = File::create(format!("{uuid}.csv"))?;
-    let mut csv_writer = csv::WriterBuilder::new()
-        .flexible(true)
-        .from_writer(output_file);
-
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .flexible(true)
-        .has_headers(false)
-        .from_reader(reader);
+    new_output: &dyn Fn(i64) -> PathBuf,
+    options: &OutputOptions,
+    tz: Tz,
+) -> Result<Pass, ISUProcessorError> {
+    let (mut reader, end) = tail::open_complete(&path, db_file.last_position_read.try_into()?)?;
 
-    csv_writer.write_record(db_file.headers.split(','))?;
+    // files first processed before per row timestamps existed have no Timestamp column yet
+    let mut headers: Vec<String> = db_file.headers.split(',').map(String::from).collect();
+    if !headers.iter().any(|h| h == TIMESTAMP_COLUMN) {
+        headers.push(String::from(TIMESTAMP_COLUMN));
+        db_file.headers = headers.join(",");
+    }
 
-    let time = db_file.time.clone();
-    for result in csv_reader.records() {
-        let mut record = result?;
+    let run_start = timestamps::parse_stored_time(db_file.time.as_str(), tz)?;
+    db_file.time = timestamps::format_time(Some(run_start));
 
-        record.push_field(time.to_string().as_str());
-        csv_writer.write_byte_record(record.as_byte_record())?;
-    }
+    let run_id = run_directory(&path)?;
+    let source = Source { path: &path, run_id: &run_id, parser: Parser::Lvm, start: db_file.last_position_read.try_into()? };
+    let mut stream = OutputStream::new(new_output, options, source, run_start, db_file.window.take(), db_file.sequence);
+    stream.start(&headers)?;
+    let position = write_engineering_rows(&mut db_file, &mut reader, end, &mut stream, run_start)?;
 
-    csv_writer.flush()?;
-    db_file.last_position_read = csv_reader.into_inner().stream_position()?.try_into()?;
-    save_file(db_file, db)?;
+    let stats = std::mem::take(&mut stream.stats);
+    let (outputs, window, sequence) = stream.finish(position)?;
+    db_file.window = window;
+    db_file.sequence = sequence;
+    db_file.last_position_read = position.try_into()?;
+    db_file.identity = Some(FileIdentity::read(&path)?);
 
-    Ok(PathBuf::from(format!("{uuid}.csv")))
+    Ok(Pass { file: Some(db_file), run_start, stats, outputs })
 }
 
 fn tail_event_process(
     mut db_file: ISUFile,
     path: PathBuf,
-    db: &Connection,
-) -> Result<PathBuf, ISUProcessorError> {
-    let uuid = Uuid::new_v4();
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    reader.seek(SeekFrom::Start(db_file.last_position_read.try_into()?))?;
+    new_output: &dyn Fn(i64) -> PathBuf,
+    options: &OutputOptions,
+    tz: Tz,
+) -> Result<Pass, ISUProcessorError> {
+    let (mut reader, end) = tail::open_complete(&path, db_file.last_position_read.try_into()?)?;
 
-    let output_file = File::create(format!("{uuid}.csv"))?;
-    let mut csv_writer = csv::WriterBuilder::new()
-        .flexible(true)
-        .from_writer(output_file);
-
-    csv_writer.write_record(db_file.headers.split(','))?;
+    // files first processed before events were parsed only had the Event, Index and DateTime columns
+    db_file.headers = EVENT_HEADERS.join(",");
 
+    // rows stored before the plugin was timezone aware hold the naive DAS time
+    let run_start = timestamps::parse_stored_time(db_file.time.as_str(), tz)?;
+    let run_id = run_directory(&path)?;
+    let source = Source { path: &path, run_id: &run_id, parser: Parser::Events, start: db_file.last_position_read.try_into()? };
+    let mut stream = OutputStream::new(new_output, options, source, run_start, None, db_file.sequence);
+    stream.start_events()?;
+    db_file.time = timestamps::format_time(Some(run_start));
     let time = db_file.time.clone();
-    let mut s = String::new();
+    let mut line = vec![];
     let mut i = db_file.last_index;
-    while let Ok(u) = reader.read_line(&mut s) {
-        if u == 0 {
+    let mut position = end;
+    loop {
+        let line_start = tail::position(&reader, end);
+        stream.read_to(line_start);
+        if reader.read_until(b'\n', &mut line)? == 0 {
             break;
         }
+        // operator notes aren't always utf-8, they're kept with the invalid bytes replaced
+        let s = String::from_utf8_lossy(&line);
 
-        s.pop();
-        csv_writer.write_record([s.as_str(), format!("{i}").as_str(), time.clone().as_str()])?;
-        s = String::new();
-        i += 1;
+        if let Some(event) = Event::parse(&s, run_start, tz) {
+            if options.stops_at(i.into()) {
+                position = line_start;
+                break;
+            }
+            stream.write(&csv::StringRecord::from(event.record(i, time.as_str()).to_vec()), i.into(), None)?;
+            i += 1;
+        }
+        line.clear();
     }
 
-    csv_writer.flush()?;
-    db_file.last_position_read = reader.stream_position()?.try_into()?;
+    let stats = std::mem::take(&mut stream.stats);
+    let (outputs, _, sequence) = stream.finish(position)?;
+    db_file.sequence = sequence;
+    db_file.last_position_read = position.try_into()?;
+    db_file.identity = Some(FileIdentity::read(&path)?);
+    db_file.accepted_rows += i64::from(i - db_file.last_index);
     db_file.last_index = i;
-    save_file(db_file, db)?;
 
-    Ok(PathBuf::from(format!("{uuid}.csv")))
+    Ok(Pass { file: Some(db_file), run_start, stats, outputs })
 }
 // fetch_file from sqlite db by path, error only on actual errors, not row not found
 fn fetch_file(path: &str, db: &Connection) -> Result<Option<ISUFile>, ISUProcessorError> {
     let path = String::from(path);
 
-    let result = db.query_row("SELECT * FROM isu WHERE path =?", [path],
+    let result = db.query_row("SELECT path, last_position_read, headers, time, last_index, accepted_rows, rejected_rows, output_sequence FROM isu WHERE path =?", [&path],
     |row| Ok(ISUFile{
         path: row.get(0)?,
         last_position_read: row.get(1)?,
         headers: row.get(2)?,
         time: row.get(3)?,
         last_index: row.get(4)?,
+        lvm_header: None,
+        identity: None,
+        accepted_rows: row.get(5)?,
+        rejected_rows: row.get(6)?,
+        window: None,
+        sequence: row.get(7)?,
     }));
 
     match result {
-        Ok(r) => Ok(Some(r)),
+        Ok(mut r) => {
+            r.lvm_header = fetch_lvm_header(&path, db)?;
+            r.identity = fetch_identity(&path, db)?;
+            r.window = fetch_window(&path, db)?;
+            Ok(Some(r))
+        }
         Err(e) => match e {
             rusqlite::Error::ExecuteReturnedResults => Ok(None),
             rusqlite::Error::QueryReturnedNoRows => Ok(None),
@@ -338,10 +1269,118 @@ fn fetch_file(path: &str, db: &Connection) -> Result<Option<ISUFile>, ISUProcess
     }
 }
 
+fn fetch_lvm_header(path: &str, db: &Connection) -> Result<Option<LvmHeader>, ISUProcessorError> {
+    let result = db.query_row("SELECT header FROM isu_lvm_header WHERE path =?", [path], |row| row.get::<_, String>(0));
+
+    match result {
+        Ok(h) => Ok(Some(serde_json::from_str(h.as_str())?)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn fetch_window(path: &str, db: &Connection) -> Result<Option<Window>, ISUProcessorError> {
+    let result = db.query_row("SELECT window FROM isu_window WHERE path =?", [path], |row| row.get::<_, String>(0));
+
+    match result {
+        Ok(w) => Ok(Some(serde_json::from_str(w.as_str())?)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// sqlite has no unsigned integers, inodes are stored with their bits reinterpreted as i64
+fn fetch_identity(path: &str, db: &Connection) -> Result<Option<FileIdentity>, ISUProcessorError> {
+    let result = db.query_row("SELECT inode, size, head_len, head_checksum FROM isu_identity WHERE path =?", [path],
+    |row| Ok(FileIdentity{
+        inode: row.get::<_, Option<i64>>(0)?.map(|i| i as u64),
+        size: row.get::<_, i64>(1)? as u64,
+        head_len: row.get::<_, i64>(2)? as u64,
+        head_checksum: row.get(3)?,
+    }));
+
+    match result {
+        Ok(i) => Ok(Some(i)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// tracked_files are the files of a run directory the plugin has state for
+fn tracked_files(directory: &Path, db: &Connection) -> Result<Vec<PathBuf>, ISUProcessorError> {
+    let mut stmt = db.prepare("SELECT path FROM isu ORDER BY path")?;
+    let paths = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+    Ok(paths
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|p| p.parent() == Some(directory))
+        .collect())
+}
+
+// is_finished is whether the file has been marked finished, those files aren't tailed any more
+fn is_finished(path: &str, db: &Connection) -> Result<bool, ISUProcessorError> {
+    Ok(db.query_row(
+        "SELECT EXISTS(SELECT 1 FROM isu WHERE path = ?1 AND finished = 1)",
+        [path],
+        |row| row.get(0),
+    )?)
+}
+
+// fetch_current fetches the stored state for a file, unless the file now at that path is not the
+// one we were tailing. In that case we return None so it's processed again from the start
+fn fetch_current(path: &Path, db: &Connection) -> Result<Option<ISUFile>, ISUProcessorError> {
+    let db_file = match fetch_file(path.to_str().ok_or(ISUProcessorError::BlankPath)?, db)? {
+        None => return Ok(None),
+        Some(f) => f,
+    };
+
+    let reason = match &db_file.identity {
+        Some(identity) => identity.changed(path)?,
+        // files tracked before identities were stored can only be checked for truncation
+        None => {
+            let len = std::fs::metadata(path)?.len();
+            (len < db_file.last_position_read.try_into()?)
+                .then(|| format!("file is shorter than the last position read {}", db_file.last_position_read))
+        }
+    };
+
+    match reason {
+        None => Ok(Some(db_file)),
+        Some(reason) => {
+            log::warn!("{} is not the file previously processed, processing it from the start: {reason}", db_file.path);
+            Ok(None)
+        }
+    }
+}
+
 // write file to sqlite db
 fn save_file(file: ISUFile, db: &Connection) -> Result<(), ISUProcessorError> {
-   let mut stmt = db.prepare("INSERT INTO isu(path, last_position_read, headers, time, last_index) VALUES (?1,?2,?3,?4,?5)")?;
-    stmt.execute([file.path, format!("{}", file.last_position_read), file.headers, file.time, format!("{}", file.last_index)])?;
+    if let Some(header) = &file.lvm_header {
+        db.execute("INSERT INTO isu_lvm_header(path, header) VALUES (?1,?2)", [file.path.clone(), serde_json::to_string(header)?])?;
+    }
+
+    if let Some(identity) = &file.identity {
+        db.execute("INSERT INTO isu_identity(path, inode, size, head_len, head_checksum) VALUES (?1,?2,?3,?4,?5)", rusqlite::params![
+            file.path,
+            identity.inode.map(|i| i as i64),
+            identity.size as i64,
+            identity.head_len as i64,
+            identity.head_checksum,
+        ])?;
+    }
+
+    // a file read from the start again mustn't continue a window of what was there before
+    match &file.window {
+        Some(window) => db.execute("INSERT INTO isu_window(path, window) VALUES (?1,?2)", [file.path.clone(), serde_json::to_string(window)?])?,
+        None => db.execute("DELETE FROM isu_window WHERE path =?", [&file.path])?,
+    };
+
+   // the row is replaced, a finished file stays finished
+   let mut stmt = db.prepare("INSERT INTO isu(path, last_position_read, headers, time, last_index, accepted_rows, rejected_rows, output_sequence, updated_at, finished) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,COALESCE((SELECT finished FROM isu WHERE path = ?1), 0))")?;
+    stmt.execute([file.path, format!("{}", file.last_position_read), file.headers, file.time, format!("{}", file.last_index), format!("{}", file.accepted_rows), format!("{}", file.rejected_rows), format!("{}", file.sequence), timestamps::format_time(Some(Utc::now()))])?;
         Ok(())
 
     /*// in order to use the Tokio runtime to do blocking operations on async functions we must
@@ -373,5 +1412,23 @@ fn save_file(file: ISUFile, db: &Connection) -> Result<(), ISUProcessorError> {
 jester_core::export_plugin!(register);
 
 extern "C" fn register(registrar: &mut dyn jester_core::PluginRegistrar) {
-    registrar.register_function(Box::new(ISUProcessor::new().unwrap()));
+    registrar.register_function(processor());
+}
+
+// processor builds the processor to register, a panic must not unwind across the FFI boundary so
+// those are caught and reported like any other construction error
+fn processor() -> Box<dyn jester_core::Processor> {
+    let reason = match std::panic::catch_unwind(ISUProcessor::new) {
+        Ok(Ok(p)) => {
+            return Box::new(SharedProcessor {
+                processor: Arc::new(p),
+                watching: Once::new(),
+            })
+        }
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => String::from("panicked while starting"),
+    };
+
+    log::error!("ISU processor unavailable: {reason}");
+    Box::new(UnavailableProcessor { reason })
 }
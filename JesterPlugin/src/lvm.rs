@@ -0,0 +1,338 @@
+use crate::errors::ISUProcessorError;
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+
+pub const END_OF_HEADER: &str = "***End_of_Header***";
+// the free text column LabVIEW ends every column header row with
+pub const COMMENT_COLUMN: &str = "Comment";
+
+// keys of a segment header, any of them in the data of a file means a new segment has started
+const SEGMENT_KEYS: [&str; 9] = [
+    "Channels",
+    "Samples",
+    "Date",
+    "Time",
+    "Y_Unit_Label",
+    "X_Dimension",
+    "X0",
+    "Delta_X",
+    "Notes",
+];
+
+// LvmHeader is the parsed header block of a LabVIEW Measurement file. An LVM file starts with a
+// file header followed by one segment header, each terminated by END_OF_HEADER
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LvmHeader {
+    pub file: LvmFileHeader,
+    pub segment: LvmSegmentHeader,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LvmFileHeader {
+    pub writer_version: Option<String>,
+    pub reader_version: Option<String>,
+    pub separator: char,
+    pub decimal_separator: char,
+    pub multi_headings: bool,
+    pub x_columns: Option<String>,
+    pub time_pref: Option<String>,
+    pub operator: Option<String>,
+    pub description: Option<String>,
+    pub project: Option<String>,
+    pub date: Option<String>,
+    pub time: Option<String>,
+}
+
+impl Default for LvmFileHeader {
+    fn default() -> Self {
+        LvmFileHeader {
+            writer_version: None,
+            reader_version: None,
+            separator: ',',
+            decimal_separator: '.',
+            multi_headings: false,
+            x_columns: None,
+            time_pref: None,
+            operator: None,
+            description: None,
+            project: None,
+            date: None,
+            time: None,
+        }
+    }
+}
+
+impl LvmFileHeader {
+    // record splits a data or column header line on the file's separator, normalizing numbers
+    pub fn record(&self, line: &str) -> StringRecord {
+        let line = line.trim_end_matches(['\r', '\n']);
+        self.normalize(line.split(self.separator).collect())
+    }
+
+    // normalize rewrites numbers written with the file's decimal separator to dot-decimal. Fields
+    // that aren't numbers, like comments, are left as written
+    pub fn normalize(&self, record: StringRecord) -> StringRecord {
+        if self.decimal_separator == '.' {
+            return record;
+        }
+
+        record
+            .iter()
+            .map(|field| {
+                let dotted = field.replace(self.decimal_separator, ".");
+                match dotted.trim().parse::<f64>() {
+                    Ok(_) => dotted,
+                    Err(_) => field.to_string(),
+                }
+            })
+            .collect()
+    }
+}
+
+// per channel values are stored in the order the channels appear in the segment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LvmSegmentHeader {
+    pub channels: usize,
+    pub samples: Vec<u64>,
+    pub date: Vec<String>,
+    pub time: Vec<String>,
+    pub y_unit_label: Vec<String>,
+    pub x_dimension: Vec<String>,
+    pub x0: Vec<f64>,
+    pub delta_x: Vec<f64>,
+    pub notes: Option<String>,
+    // row index in the file the segment starts at, sample times without an X value count from here
+    #[serde(default)]
+    pub first_row: i64,
+}
+
+impl LvmSegmentHeader {
+    // sample rate in samples per X unit (normally seconds) for the channel, if Delta_X was recorded
+    pub fn sample_rate(&self, channel: usize) -> Option<f64> {
+        match self.delta_x.get(channel) {
+            Some(d) if *d > 0.0 => Some(1.0 / d),
+            _ => None,
+        }
+    }
+
+    pub fn unit(&self, channel: usize) -> Option<&str> {
+        self.y_unit_label
+            .get(channel)
+            .map(|u| u.as_str())
+            .filter(|u| !u.is_empty())
+    }
+}
+
+// read_header consumes the file and segment headers from the reader, leaving it positioned at the
+// column header row of the first segment. Returns None if the reader ends first, the DAS is still
+// writing the header
+pub fn read_header<R: BufRead>(reader: &mut R) -> Result<Option<LvmHeader>, ISUProcessorError> {
+    let mut header = LvmHeader::default();
+    let mut separator: Option<char> = None;
+    let mut header_count = 0;
+
+    // total of two headers in this file, the file header and the segment header
+    while header_count < 2 {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.starts_with(END_OF_HEADER) {
+            header_count += 1;
+            continue;
+        }
+
+        let (key, sep, rest) = match split_key(line, separator) {
+            None => continue,
+            Some(s) => s,
+        };
+
+        if header_count == 0 {
+            parse_file_field(&mut header.file, key, sep, rest)?;
+            if key == "Separator" {
+                separator = Some(header.file.separator);
+            }
+        } else {
+            parse_segment_field(&mut header.segment, &header.file, key, sep, rest)?;
+        }
+    }
+
+    Ok(Some(header))
+}
+
+// is_segment_start is true for a line that opens another segment header in the data of a file
+pub fn is_segment_start(line: &str, file: &LvmFileHeader) -> bool {
+    line.starts_with(END_OF_HEADER)
+        || split_key(line, Some(file.separator))
+            .is_some_and(|(key, _, _)| SEGMENT_KEYS.contains(&key))
+}
+
+// read_segment reads a segment header that starts with first_line, through its END_OF_HEADER and
+// the column header row after it. Returns None if the reader ends first, the DAS is still writing
+// the header
+pub fn read_segment<R: BufRead>(
+    first_line: &str,
+    reader: &mut R,
+    file: &LvmFileHeader,
+) -> Result<Option<(LvmSegmentHeader, StringRecord)>, ISUProcessorError> {
+    let mut segment = LvmSegmentHeader::default();
+    let mut line = first_line.to_string();
+
+    while !line.starts_with(END_OF_HEADER) {
+        if let Some((key, sep, rest)) = split_key(line.as_str(), Some(file.separator)) {
+            parse_segment_field(&mut segment, file, key, sep, rest)?;
+        }
+
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        line.truncate(line.trim_end_matches(['\r', '\n']).len());
+    }
+
+    let mut columns = String::new();
+    if reader.read_line(&mut columns)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((segment, file.record(columns.as_str()))))
+}
+
+// split_key returns the key of a header line, the separator it was written with and the remainder
+// of the line. Until the file header declares its Separator we accept either tab or comma
+fn split_key(line: &str, separator: Option<char>) -> Option<(&str, char, &str)> {
+    let index = match separator {
+        Some(s) => line.find(s)?,
+        None => line.find(['\t', ','])?,
+    };
+
+    let sep = line[index..].chars().next()?;
+    let key = line[..index].trim();
+    if key.is_empty() {
+        return None;
+    }
+
+    Some((key, sep, &line[index + sep.len_utf8()..]))
+}
+
+fn values(rest: &str, sep: char) -> Vec<String> {
+    let mut values: Vec<String> = rest.split(sep).map(|v| v.trim().to_string()).collect();
+    while values.last().is_some_and(|v| v.is_empty()) {
+        values.pop();
+    }
+
+    values
+}
+
+fn first_value(rest: &str, sep: char) -> Option<String> {
+    values(rest, sep)
+        .into_iter()
+        .next()
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_separator(value: &str) -> Result<char, ISUProcessorError> {
+    match value {
+        "Tab" => Ok('\t'),
+        "Comma" => Ok(','),
+        "Semicolon" => Ok(';'),
+        "Space" => Ok(' '),
+        v if v.chars().count() == 1 => Ok(v.chars().next().unwrap_or(',')),
+        v => Err(ISUProcessorError::LvmHeaderError(format!(
+            "unknown separator {v}"
+        ))),
+    }
+}
+
+fn parse_file_field(
+    file: &mut LvmFileHeader,
+    key: &str,
+    sep: char,
+    rest: &str,
+) -> Result<(), ISUProcessorError> {
+    match key {
+        "Writer_Version" => file.writer_version = first_value(rest, sep),
+        "Reader_Version" => file.reader_version = first_value(rest, sep),
+        "Separator" => {
+            if let Some(v) = first_value(rest, sep) {
+                file.separator = parse_separator(v.as_str())?
+            }
+        }
+        // the decimal separator may itself be the field separator, so take the raw character
+        "Decimal_Separator" => {
+            if let Some(c) = rest.chars().next() {
+                file.decimal_separator = c
+            }
+        }
+        "Multi_Headings" => file.multi_headings = first_value(rest, sep).as_deref() == Some("Yes"),
+        "X_Columns" => file.x_columns = first_value(rest, sep),
+        "Time_Pref" => file.time_pref = first_value(rest, sep),
+        "Operator" => file.operator = first_value(rest, sep),
+        "Description" => file.description = first_value(rest, sep),
+        "Project" => file.project = first_value(rest, sep),
+        "Date" => file.date = first_value(rest, sep),
+        "Time" => file.time = first_value(rest, sep),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn parse_segment_field(
+    segment: &mut LvmSegmentHeader,
+    file: &LvmFileHeader,
+    key: &str,
+    sep: char,
+    rest: &str,
+) -> Result<(), ISUProcessorError> {
+    match key {
+        "Channels" => {
+            segment.channels = match first_value(rest, sep) {
+                None => 0,
+                Some(v) => v.parse().map_err(|_| {
+                    ISUProcessorError::LvmHeaderError(format!("invalid channel count {v}"))
+                })?,
+            }
+        }
+        "Samples" => {
+            segment.samples = values(rest, sep)
+                .iter()
+                .filter(|v| !v.is_empty())
+                .map(|v| {
+                    v.parse().map_err(|_| {
+                        ISUProcessorError::LvmHeaderError(format!("invalid sample count {v}"))
+                    })
+                })
+                .collect::<Result<Vec<u64>, ISUProcessorError>>()?
+        }
+        "Date" => segment.date = values(rest, sep),
+        "Time" => segment.time = values(rest, sep),
+        "Y_Unit_Label" => segment.y_unit_label = values(rest, sep),
+        "X_Dimension" => segment.x_dimension = values(rest, sep),
+        "X0" => segment.x0 = parse_floats(rest, sep, file.decimal_separator)?,
+        "Delta_X" => segment.delta_x = parse_floats(rest, sep, file.decimal_separator)?,
+        "Notes" => segment.notes = first_value(rest, sep),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn parse_floats(
+    rest: &str,
+    sep: char,
+    decimal_separator: char,
+) -> Result<Vec<f64>, ISUProcessorError> {
+    values(rest, sep)
+        .iter()
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.replace(decimal_separator, ".")
+                .parse()
+                .map_err(|_| ISUProcessorError::LvmHeaderError(format!("invalid number {v}")))
+        })
+        .collect()
+}
@@ -0,0 +1,76 @@
+use crate::errors::ISUProcessorError;
+use crate::format::{self, ColumnType};
+use crate::graph;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+pub const PLUGIN_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Parser is how the rows of a DAS file were read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Parser {
+    Lvm,
+    Events,
+}
+
+// Source is the DAS file a pass reads rows from and where in it the pass started
+#[derive(Debug, Clone, Copy)]
+pub struct Source<'a> {
+    pub path: &'a Path,
+    pub run_id: &'a str,
+    pub parser: Parser,
+    pub start: u64,
+}
+
+// Manifest traces an output back to the bytes of the DAS file its rows were read from. The byte
+// range runs from where the output was started to where it was cut, end exclusive, so the chunks of
+// a file cover it without overlapping. Indexes are of the DAS rows read while the output was open,
+// an output of windows can hold rows of earlier passes as well
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub output: String, // file name of the output
+    pub source: PathBuf,
+    pub run_id: String,
+    pub parser: Parser,
+    pub byte_range: (u64, u64),
+    pub first_index: Option<i64>,
+    pub last_index: Option<i64>,
+    pub rows: u64, // rows in the output
+    pub sha256: String,
+    pub schema_hash: String,
+    pub plugin_version: String,
+}
+
+// manifest_path is where the manifest of an output is written, next to the output
+pub fn manifest_path(output: &Path) -> PathBuf {
+    format::sibling_path(output, "manifest", "json")
+}
+
+// schema_hash is the sha-256 of an output's column names and types, outputs with the same hash
+// can be read the same way
+pub fn schema_hash(names: &[String], types: &[ColumnType]) -> String {
+    let mut hasher = Sha256::new();
+    for (i, name) in names.iter().enumerate() {
+        let column_type = types.get(i).copied().unwrap_or(ColumnType::Text);
+        hasher.update(format!("{name}:{column_type:?}\n").as_bytes());
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+// file_hash is the sha-256 of a file's contents
+pub fn file_hash(path: &Path) -> Result<String, ISUProcessorError> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// write writes the manifest to the part file of path, as graph records so DeepLynx keeps it with
+// the output's node rather than importing it as data
+pub fn write(manifest: &Manifest, path: &Path) -> Result<(), ISUProcessorError> {
+    graph::write_records(&graph::manifest_records(manifest)?, path)
+}
@@ -0,0 +1,171 @@
+use crate::errors::ISUProcessorError;
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+
+// Migration is a single, ordered change to the state database. Migrations run once each, in order
+// of version, and never change after they've shipped - add a new one instead
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    run: fn(&Transaction) -> Result<(), rusqlite::Error>,
+}
+
+pub const MIGRATIONS: [Migration; 9] = [
+    Migration {
+        version: 1,
+        description: "file tail positions",
+        run: tail_positions,
+    },
+    Migration {
+        version: 2,
+        description: "parsed LVM headers",
+        run: |tx| {
+            // the parsed LVM header is stored as json in its own table so that existing isu tables keep working
+            tx.execute("CREATE TABLE IF NOT EXISTS isu_lvm_header (path text UNIQUE ON CONFLICT REPLACE, header text);", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 3,
+        description: "file identities",
+        run: |tx| {
+            tx.execute("CREATE TABLE IF NOT EXISTS isu_identity (path text UNIQUE ON CONFLICT REPLACE, inode integer, size integer, head_len integer, head_checksum integer);", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 4,
+        description: "output journal",
+        run: |tx| {
+            tx.execute("CREATE TABLE IF NOT EXISTS isu_pending_output (path text PRIMARY KEY, source text, channel text, sent integer DEFAULT 0);", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 5,
+        description: "validation counts",
+        run: |tx| {
+            tx.execute_batch(
+                "ALTER TABLE isu ADD COLUMN accepted_rows integer NOT NULL DEFAULT 0;
+                 ALTER TABLE isu ADD COLUMN rejected_rows integer NOT NULL DEFAULT 0;",
+            )
+        },
+    },
+    Migration {
+        version: 6,
+        description: "aggregation windows",
+        run: |tx| {
+            tx.execute("CREATE TABLE IF NOT EXISTS isu_window (path text UNIQUE ON CONFLICT REPLACE, window text);", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 7,
+        description: "output sequence numbers",
+        run: |tx| {
+            tx.execute("ALTER TABLE isu ADD COLUMN output_sequence integer NOT NULL DEFAULT 0", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 8,
+        description: "file update times and finished files",
+        run: |tx| {
+            tx.execute_batch(
+                "ALTER TABLE isu ADD COLUMN updated_at text;
+                 ALTER TABLE isu ADD COLUMN finished integer NOT NULL DEFAULT 0;",
+            )
+        },
+    },
+    Migration {
+        version: 9,
+        description: "runs",
+        run: |tx| {
+            tx.execute("CREATE TABLE IF NOT EXISTS isu_run (directory text PRIMARY KEY, run_start text, last_activity text, stats text, closed_at text);", [])?;
+            Ok(())
+        },
+    },
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+// current_version is the last migration applied to the database, 0 if it has never been migrated
+pub fn current_version(db: &Connection) -> Result<i64, ISUProcessorError> {
+    let versioned: bool = db.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'isu_schema_version')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !versioned {
+        return Ok(0);
+    }
+
+    Ok(db.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM isu_schema_version",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+// migrate brings the database up to the latest version. Databases from before versioning already
+// hold the tables of the early migrations, those migrations adopt what's there rather than
+// recreating it so tail positions are kept
+pub fn migrate(db: &Connection) -> Result<(), ISUProcessorError> {
+    create_version_table(db)?;
+    let current = current_version(db)?;
+    if current > latest_version() {
+        return Err(ISUProcessorError::MigrationError(format!(
+            "state database is at version {current}, newer than this plugin's {}",
+            latest_version()
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        // each migration commits on its own, a failure leaves the database at the previous version
+        let tx = Transaction::new_unchecked(db, TransactionBehavior::Immediate)?;
+        (migration.run)(&tx).map_err(|e| {
+            ISUProcessorError::MigrationError(format!(
+                "migration {} ({}) failed: {e}",
+                migration.version, migration.description
+            ))
+        })?;
+        tx.execute(
+            "INSERT INTO isu_schema_version(version, description, applied_at) VALUES (?1, ?2, datetime('now'))",
+            (migration.version, migration.description),
+        )?;
+        tx.commit()?;
+
+        log::info!(
+            "migrated state database to version {} ({})",
+            migration.version,
+            migration.description
+        );
+    }
+
+    Ok(())
+}
+
+fn create_version_table(db: &Connection) -> Result<(), ISUProcessorError> {
+    db.execute("CREATE TABLE IF NOT EXISTS isu_schema_version (version integer PRIMARY KEY, description text, applied_at text);", [])?;
+    Ok(())
+}
+
+fn tail_positions(tx: &Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("CREATE TABLE IF NOT EXISTS isu (path text UNIQUE ON CONFLICT REPLACE, last_position_read integer, headers text, time text, last_index integer);", [])?;
+
+    // the earliest isu tables didn't count rows, those files are continued from index 0
+    let has_last_index: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('isu') WHERE name = 'last_index')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_last_index {
+        tx.execute(
+            "ALTER TABLE isu ADD COLUMN last_index integer NOT NULL DEFAULT 0",
+            [],
+        )?;
+    }
+
+    Ok(())
+}
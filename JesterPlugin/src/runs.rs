@@ -0,0 +1,277 @@
+use crate::aggregate::ChannelStats;
+use crate::channels::ChannelMap;
+use crate::errors::ISUProcessorError;
+use crate::format::ColumnType;
+use crate::graph::NON_CHANNEL_COLUMNS;
+use crate::timestamps;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use csv::StringRecord;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// channels mapped to this unit are reactor power readings
+pub const POWER_UNIT: &str = "W";
+
+// the column every output row carries its run's id in. A run's id is the name of its run
+// directory, which is also what its graph node is keyed by
+pub const RUN_ID_COLUMN: &str = "RunId";
+
+// RunStats are gathered from the rows of a run's files, across passes
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunStats {
+    pub accepted_rows: i64,
+    pub rejected_rows: i64,
+    pub last_sample: Option<DateTime<Utc>>,
+    pub channels: BTreeMap<String, ChannelStats>, // keyed by output name
+    pub peak_power: Option<PeakPower>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeakPower {
+    pub channel: String,
+    pub watts: f64,
+    pub time: Option<DateTime<Utc>>,
+}
+
+// RunColumns are the columns of a file's rows that statistics are kept for
+#[derive(Debug, Clone, Default)]
+pub struct RunColumns {
+    columns: Vec<(usize, String, bool)>, // index, output name and whether it's a power reading
+}
+
+impl RunColumns {
+    pub fn new(
+        headers: &[String],
+        names: &[String],
+        types: &[ColumnType],
+        channels: &ChannelMap,
+    ) -> RunColumns {
+        let columns = headers
+            .iter()
+            .zip(names)
+            .zip(types)
+            .enumerate()
+            .filter(|(_, ((h, _), t))| {
+                **t == ColumnType::Float && !NON_CHANNEL_COLUMNS.contains(&h.as_str())
+            })
+            .map(|(i, ((h, n), _))| (i, n.clone(), channels.unit(h) == Some(POWER_UNIT)))
+            .collect();
+
+        RunColumns { columns }
+    }
+}
+
+impl RunStats {
+    pub fn add(&mut self, columns: &RunColumns, record: &StringRecord, time: Option<DateTime<Utc>>) {
+        self.accepted_rows += 1;
+        self.last_sample = self.last_sample.max(time);
+
+        for (i, name, power) in &columns.columns {
+            let value = match record.get(*i).map(|v| v.trim().parse::<f64>()) {
+                Some(Ok(v)) if v.is_finite() => v,
+                _ => continue,
+            };
+
+            match self.channels.get_mut(name) {
+                None => {
+                    self.channels.insert(name.clone(), ChannelStats::new(value));
+                }
+                Some(s) => s.add(value),
+            }
+
+            if *power && self.peak_power.as_ref().is_none_or(|p| value > p.watts) {
+                self.peak_power = Some(PeakPower {
+                    channel: name.clone(),
+                    watts: value,
+                    time,
+                });
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &RunStats) {
+        self.accepted_rows += other.accepted_rows;
+        self.rejected_rows += other.rejected_rows;
+        self.last_sample = self.last_sample.max(other.last_sample);
+        for (name, stats) in &other.channels {
+            match self.channels.get_mut(name) {
+                None => {
+                    self.channels.insert(name.clone(), stats.clone());
+                }
+                Some(s) => s.merge(stats),
+            }
+        }
+
+        if let Some(peak) = &other.peak_power {
+            if self.peak_power.as_ref().is_none_or(|p| peak.watts > p.watts) {
+                self.peak_power = Some(peak.clone());
+            }
+        }
+    }
+
+    fn rows(&self) -> i64 {
+        self.accepted_rows + self.rejected_rows
+    }
+}
+
+// Run is a DAS run directory the plugin has read files of
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub directory: PathBuf,
+    pub run_start: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>, // when rows were last read from the run
+    pub stats: RunStats,
+}
+
+impl Run {
+    pub fn name(&self) -> String {
+        self.directory
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    // end is the time of the last sample, or when rows were last read for runs whose rows had no
+    // sample times
+    pub fn end(&self) -> DateTime<Utc> {
+        self.stats.last_sample.unwrap_or(self.last_activity)
+    }
+}
+
+// record adds the stats of a pass over one of the run's files. Runs are opened by the first pass
+// over any of their files, closed runs keep their stats but aren't opened again
+pub fn record(
+    db: &Connection,
+    directory: &Path,
+    run_start: DateTime<Utc>,
+    stats: &RunStats,
+    now: DateTime<Utc>,
+) -> Result<(), ISUProcessorError> {
+    let key = directory.to_string_lossy();
+    let stored: Option<(String, Option<String>)> = db
+        .query_row(
+            "SELECT stats, closed_at FROM isu_run WHERE directory = ?1",
+            [key.as_ref()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    // a pass that read nothing isn't activity, unless it's what opens the run
+    let mut total = match stored {
+        None => RunStats::default(),
+        Some((_, Some(_))) => {
+            if stats.rows() > 0 {
+                log::warn!("{key} has been closed, rows read from it now aren't in its summary");
+            }
+            return Ok(());
+        }
+        Some(_) if stats.rows() == 0 => return Ok(()),
+        Some((s, None)) => serde_json::from_str(s.as_str())?,
+    };
+
+    total.merge(stats);
+    db.execute(
+        "INSERT INTO isu_run(directory, run_start, last_activity, stats) VALUES (?1,?2,?3,?4)
+         ON CONFLICT(directory) DO UPDATE SET last_activity = ?3, stats = ?4",
+        [
+            key.as_ref(),
+            timestamps::format_time(Some(run_start)).as_str(),
+            timestamps::format_time(Some(now)).as_str(),
+            serde_json::to_string(&total)?.as_str(),
+        ],
+    )?;
+
+    Ok(())
+}
+
+// ended are the open runs that have ended. A run has ended once a run that started after it has
+// been seen, or when nothing has been read from it for the idle timeout
+pub fn ended(
+    db: &Connection,
+    now: DateTime<Utc>,
+    idle_timeout: Option<Duration>,
+    tz: Tz,
+) -> Result<Vec<Run>, ISUProcessorError> {
+    let runs = {
+        let mut stmt = db.prepare(
+            "SELECT directory, run_start, last_activity, stats, closed_at IS NULL FROM isu_run",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, bool>(4)?,
+            ))
+        })?;
+        rows.collect::<Result<Vec<(String, String, String, String, bool)>, rusqlite::Error>>()?
+    };
+
+    let mut latest = None;
+    let mut open = vec![];
+    for (directory, run_start, last_activity, stats, is_open) in runs {
+        let run_start = timestamps::parse_stored_time(run_start.as_str(), tz)?;
+        latest = latest.max(Some(run_start));
+        if is_open {
+            open.push(Run {
+                directory: PathBuf::from(directory),
+                run_start,
+                last_activity: timestamps::parse_stored_time(last_activity.as_str(), tz)?,
+                stats: serde_json::from_str(stats.as_str())?,
+            });
+        }
+    }
+
+    Ok(open
+        .into_iter()
+        .filter(|run| {
+            let superseded = latest.is_some_and(|l| run.run_start < l);
+            let idle = idle_timeout.is_some_and(|t| now - run.last_activity >= t);
+            superseded || idle
+        })
+        .collect())
+}
+
+// is_closed is whether the run has been closed, its summary has been sent and rows read from it
+// aren't counted any more
+pub fn is_closed(db: &Connection, directory: &Path) -> Result<bool, ISUProcessorError> {
+    let closed: Option<bool> = db
+        .query_row(
+            "SELECT closed_at IS NOT NULL FROM isu_run WHERE directory = ?1",
+            [directory.to_string_lossy().as_ref()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(closed.unwrap_or(false))
+}
+
+// replace sets the stats of an open run, for when they're counted again from its files
+pub fn replace(db: &Connection, directory: &Path, stats: &RunStats) -> Result<(), ISUProcessorError> {
+    db.execute(
+        "UPDATE isu_run SET stats = ?2 WHERE directory = ?1 AND closed_at IS NULL",
+        [
+            directory.to_string_lossy().as_ref(),
+            serde_json::to_string(stats)?.as_str(),
+        ],
+    )?;
+
+    Ok(())
+}
+
+// close marks the run closed, it isn't opened again
+pub fn close(db: &Connection, run: &Run, now: DateTime<Utc>) -> Result<(), ISUProcessorError> {
+    db.execute(
+        "UPDATE isu_run SET closed_at = ?2 WHERE directory = ?1",
+        [
+            run.directory.to_string_lossy().as_ref(),
+            timestamps::format_time(Some(now)).as_str(),
+        ],
+    )?;
+
+    Ok(())
+}
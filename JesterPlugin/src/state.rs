@@ -0,0 +1,45 @@
+use crate::errors::ISUProcessorError;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// how long a connection waits on another process holding the database, e.g. the isu-state binary
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// StateStore guards the connection to the state database so Jester can process files from several
+// threads. The connection is only held while state is read or committed, files are read in
+// between. Passes over the same file are run one at a time by locking the file's path
+pub struct StateStore {
+    conn: Mutex<Connection>,
+    files: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+}
+
+impl StateStore {
+    pub fn open(path: &Path) -> Result<StateStore, ISUProcessorError> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        Ok(StateStore {
+            conn: Mutex::new(conn),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // lock waits for the connection, it's held until the guard is dropped
+    pub fn lock(&self) -> Result<MutexGuard<'_, Connection>, ISUProcessorError> {
+        self.conn.lock().map_err(|_| ISUProcessorError::ThreadError)
+    }
+
+    // file_lock is the lock of a file's path, shared by every pass over the file. Locks no pass
+    // holds are dropped as new ones are handed out
+    pub fn file_lock(&self, path: &Path) -> Result<Arc<Mutex<()>>, ISUProcessorError> {
+        let mut files = self
+            .files
+            .lock()
+            .map_err(|_| ISUProcessorError::ThreadError)?;
+        files.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+        Ok(files.entry(path.to_path_buf()).or_default().clone())
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+use std::path::Path;
+
+const SCAN_CHUNK: u64 = 8 * 1024;
+
+// open_complete opens the file at start and limits the reader to the complete lines written so far.
+// The DAS may be in the middle of writing a line, that trailing fragment is left unread so it's
+// picked up whole on the next call. Returns the reader and the position just past the last newline,
+// which is where the next call should start
+pub fn open_complete(path: &Path, start: u64) -> io::Result<(BufReader<Take<File>>, u64)> {
+    let mut file = File::open(path)?;
+    let end = complete_end(&mut file, start)?;
+
+    file.seek(SeekFrom::Start(start))?;
+    Ok((BufReader::new(file.take(end - start)), end))
+}
+
+// position is where the next line the reader returns starts in the file, end being the position
+// open_complete returned
+pub fn position(reader: &BufReader<Take<File>>, end: u64) -> u64 {
+    end - reader.get_ref().limit() - reader.buffer().len() as u64
+}
+
+// complete_end scans backwards from the end of the file for the last newline at or after start
+fn complete_end(file: &mut File, start: u64) -> io::Result<u64> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut chunk_end = len;
+    let mut buf = vec![0; SCAN_CHUNK as usize];
+
+    while chunk_end > start {
+        let chunk_start = chunk_end.saturating_sub(SCAN_CHUNK).max(start);
+        let chunk = &mut buf[..(chunk_end - chunk_start) as usize];
+
+        file.seek(SeekFrom::Start(chunk_start))?;
+        file.read_exact(chunk)?;
+
+        if let Some(i) = chunk.iter().rposition(|b| *b == b'\n') {
+            return Ok(chunk_start + i as u64 + 1);
+        }
+
+        chunk_end = chunk_start;
+    }
+
+    Ok(start)
+}
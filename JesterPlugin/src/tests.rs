@@ -1,669 +1,2831 @@
-use jester_core::errors::ProcessorError;
 /*
 TEST DATA HAS BEEN REMOVED FOR OPEN SOURCING - CONTACT US IF YOU NEED TO TEST
  */
 
 #[cfg(test)]
 mod general_tests {
-    use crate::{fetch_file, save_file, ISUFile, ISUProcessor};
-    use adler::adler32;
+    use crate::config::Configuration;
+    use crate::{create_tables, fetch_file, save_file, ISUFile, ISUProcessor};
     use jester_core::{DataSourceMessage, Processor};
-    use sqlx::sqlite::SqliteConnectOptions;
+    use rusqlite::Connection;
     use sqlx::SqlitePool;
-    use std::fs::{File, OpenOptions};
-    use std::io::{BufReader, Write};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
     use std::path::{Path, PathBuf};
-    use std::str::FromStr;
-    use tokio::fs;
-    use tokio::sync::mpsc::unbounded_channel;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+    use uuid::Uuid;
+
+    // the DAS test data was removed for open sourcing, these files stand in for a run directory
+    const ENGINEERING_DATA: &str = "LabVIEW Measurement,\r
+Separator,Comma\r
+Decimal_Separator,.\r
+***End_of_Header***\r
+,\r
+Channels,2,\r
+Samples,1,1,\r
+X0,0.0000000000000000E+0,0.0000000000000000E+0,\r
+Delta_X,1.000000,1.000000,\r
+***End_of_Header***\r
+X_Value,Ch1 (CPS),CCR_cm,Comment\r
+0.000000,7123.633812,24.506584\r
+1.000000,7130.102210,24.511002\r
+";
+
+    const EVENTS: &str = "2/13/2023 2:31:05 PM,Coarse Rod 1 inserted,\r
+2/13/2023 2:31:40 PM,Mode changed to Auto,\r
+";
+
+    fn test_file(headers: &str) -> ISUFile {
+        ISUFile {
+            path: String::from("test"),
+            last_position_read: 0,
+            last_index: 0,
+            headers: String::from(headers),
+            time: String::from(""),
+            lvm_header: None,
+            identity: None,
+            accepted_rows: 0,
+            rejected_rows: 0,
+            window: None,
+            sequence: 0,
+        }
+    }
 
-    #[tokio::test]
-    async fn init_test() {
-        fs::remove_file(".test.db").await.unwrap();
+    fn run_directory() -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(Uuid::new_v4().to_string())
+            .join("Feb_13_2023_14_29");
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 
-        let isu = match ISUProcessor::new() {
+    // processor keeps its state and outputs in the run directory so tests don't share them
+    async fn processor(dir: &Path) -> ISUProcessor {
+        let isu = match ISUProcessor::with_config(Configuration {
+            state_db: dir.join("state.db"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
+        }) {
             Ok(p) => p,
             Err(e) => {
-                panic!("init_test failed {e:?}")
+                panic!("unable to create the processor {e:?}")
             }
         };
 
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}",)
-            }
-        };
+        // jester's own database isn't used, the plugin keeps its state in state_db
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let result = isu.init(db);
+        assert!(result.is_ok(), "{:?}", result.err());
+        isu
+    }
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}",)
+    // process runs the plugin over a file and returns the contents of the output it sent, the
+    // graph records and manifests sent with it are removed
+    async fn process(isu: &ISUProcessor, path: &Path) -> String {
+        let (ts_tx, mut ts_rx) = unbounded_channel();
+        let (g_tx, mut g_rx) = unbounded_channel();
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+        let result = isu.process(path.to_path_buf(), db, Some(ts_tx), Some(g_tx));
+        assert!(result.is_ok(), "Error during process call: {result:?}");
+
+        while let Ok(DataSourceMessage::File((f, _))) = g_rx.try_recv() {
+            fs::remove_file(f).unwrap();
+        }
+        received(&mut ts_rx)
+    }
+
+    // received reads and removes the single output sent on the timeseries channel
+    fn received(rx: &mut UnboundedReceiver<DataSourceMessage>) -> String {
+        let mut outputs = vec![];
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                DataSourceMessage::File((f, _)) => outputs.push(f),
+                _ => panic!("wrong message type received"),
             }
-        };
+        }
+        assert_eq!(outputs.len(), 1, "{outputs:?}");
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+        let generated = outputs.remove(0);
+        let contents = fs::read_to_string(&generated).unwrap();
+        fs::remove_file(generated).unwrap();
+        contents
+    }
+
+    fn append(path: &Path, data: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file.flush().unwrap();
+    }
 
-        let mut row =
-            sqlx::query("SELECT name FROM sqlite_master WHERE type='table' AND name='isu'")
-                .fetch_one(&db)
-                .await;
+    #[tokio::test]
+    async fn init_test() {
+        let dir = run_directory();
+        let isu = processor(&dir).await;
+
+        let row: rusqlite::Result<String> = isu.state.lock().unwrap().query_row(
+            "SELECT name FROM sqlite_master WHERE type='table' AND name='isu'",
+            [],
+            |row| row.get(0),
+        );
         // will be Ok if it returns a single row, which corresponds to the table existing
-        assert!(row.is_ok())
+        assert!(row.is_ok());
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
     }
 
     #[tokio::test]
     async fn process_engineering_data_test() {
-        fs::remove_file(".test.db").await;
-        let (ts_tx, mut ts_rx) = unbounded_channel();
-        let (g_tx, mut g_rx) = unbounded_channel();
+        let dir = run_directory();
+        let isu = processor(&dir).await;
+        let path = dir.join("Most Engineering Data.txt");
+        fs::write(&path, ENGINEERING_DATA).unwrap();
+
+        assert_eq!(
+            process(&isu, &path).await,
+            "X_Value,Ch1 (CPS),CCR_cm,Comment,DateTime,Timestamp,RunId
+0.000000,7123.633812,24.506584,,2023-02-13T14:29:00+00:00,2023-02-13T14:29:00+00:00,Feb_13_2023_14_29
+1.000000,7130.102210,24.511002,,2023-02-13T14:29:00+00:00,2023-02-13T14:29:01+00:00,Feb_13_2023_14_29
+"
+        );
 
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
-        };
+        // now we're testing the tailing, only the new record should be sent
+        append(&path, "2.000000,7135.880013,24.514277\r\n");
+        assert_eq!(
+            process(&isu, &path).await,
+            "X_Value,Ch1 (CPS),CCR_cm,Comment,DateTime,Timestamp,RunId
+2.000000,7135.880013,24.514277,,2023-02-13T14:29:00+00:00,2023-02-13T14:29:02+00:00,Feb_13_2023_14_29
+"
+        );
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn process_event_test() {
+        let dir = run_directory();
+        let isu = processor(&dir).await;
+        let path = dir.join("Events.txt");
+        fs::write(&path, EVENTS).unwrap();
+
+        assert_eq!(
+            process(&isu, &path).await,
+            r#"Event,Index,DateTime,EventTime,Category,Component,Text,RunId
+"2/13/2023 2:31:05 PM,Coarse Rod 1 inserted",0,2023-02-13T14:29:00+00:00,2023-02-13T14:31:05+00:00,rod_motion,Coarse Rod 1,Coarse Rod 1 inserted,Feb_13_2023_14_29
+"2/13/2023 2:31:40 PM,Mode changed to Auto",1,2023-02-13T14:29:00+00:00,2023-02-13T14:31:40+00:00,mode_change,,Mode changed to Auto,Feb_13_2023_14_29
+"#
+        );
+
+        // now we're testing the tailing, the index carries on from the first pass
+        append(&path, "2/13/2023 2:32:10 PM,Coarse Rod 1 withdrawn,\r\n");
+        assert_eq!(
+            process(&isu, &path).await,
+            r#"Event,Index,DateTime,EventTime,Category,Component,Text,RunId
+"2/13/2023 2:32:10 PM,Coarse Rod 1 withdrawn",2,2023-02-13T14:29:00+00:00,2023-02-13T14:32:10+00:00,rod_motion,Coarse Rod 1,Coarse Rod 1 withdrawn,Feb_13_2023_14_29
+"#
+        );
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn save_file_test() {
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        let result = save_file(test_file("header1, header2"), &db);
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        // saving the same path should result in an ok as well, as it will upsert the existing record
+        let result = save_file(test_file("header1, header2"), &db);
+        assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    #[test]
+    fn fetch_file_test() {
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        let result = save_file(test_file("header1,header2"), &db);
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        let result = fetch_file("test", &db);
+        assert!(result.is_ok(), "{:?}", result.err());
+        let result = result.unwrap();
+        assert!(result.is_some());
+        assert_eq!(result.unwrap().headers, String::from("header1,header2"));
+
+        let result = save_file(test_file("header3,header4"), &db);
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        let result = fetch_file("test", &db);
+        assert!(result.is_ok(), "{:?}", result.err());
+        let result = result.unwrap();
+        assert!(result.is_some());
+        assert_eq!(result.unwrap().headers, String::from("header3,header4"))
+    }
+}
+
+#[cfg(test)]
+mod lvm_tests {
+    use crate::lvm::read_header;
+    use csv::StringRecord;
+    use std::io::{BufRead, Cursor};
+
+    const HEADER: &str = "LabVIEW Measurement\t
+Writer_Version\t2
+Reader_Version\t2
+Separator\tTab
+Decimal_Separator\t,
+Multi_Headings\tNo
+X_Columns\tOne
+Time_Pref\tAbsolute
+Operator\tISU
+Description\tAGN-201 startup
+Date\t2023/02/13
+Time\t14:29:01,5
+***End_of_Header***
+\t
+Channels\t2\t
+Samples\t1\t1\t
+Date\t2023/02/13\t2023/02/13\t
+Time\t14:29:01,5\t14:29:01,5\t
+Y_Unit_Label\tCPS\tcm\t
+X_Dimension\tTime\tTime\t
+X0\t0,0000000000000000E+0\t0,0000000000000000E+0\t
+Delta_X\t0,500000\t0,500000\t
+***End_of_Header***
+X_Value\tCh1\tCCR\tComment
+";
+
+    #[test]
+    fn read_header_test() {
+        let mut reader = Cursor::new(HEADER);
+        let header = read_header(&mut reader).unwrap().unwrap();
+
+        assert_eq!(header.file.separator, '\t');
+        assert_eq!(header.file.decimal_separator, ',');
+        assert_eq!(header.file.operator.as_deref(), Some("ISU"));
+        assert_eq!(header.file.description.as_deref(), Some("AGN-201 startup"));
+        assert_eq!(header.file.date.as_deref(), Some("2023/02/13"));
+        assert!(!header.file.multi_headings);
+
+        assert_eq!(header.segment.channels, 2);
+        assert_eq!(header.segment.samples, vec![1, 1]);
+        assert_eq!(header.segment.x0, vec![0.0, 0.0]);
+        assert_eq!(header.segment.delta_x, vec![0.5, 0.5]);
+        assert_eq!(header.segment.unit(1), Some("cm"));
+        assert_eq!(header.segment.sample_rate(0), Some(2.0));
+
+        // the reader should be left at the column header row
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("X_Value"));
+    }
+
+    #[test]
+    fn read_truncated_header_test() {
+        let mut reader = Cursor::new("LabVIEW Measurement,\nSeparator,Comma\n***End_of_Header***\n");
+        assert!(read_header(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn normalize_test() {
+        let header = read_header(&mut Cursor::new(HEADER)).unwrap().unwrap();
+        assert_eq!(
+            header.file.record("1,500000\t-2,5E-3\t12\tvalve open, rod 2\r\n"),
+            StringRecord::from(vec!["1.500000", "-2.5E-3", "12", "valve open, rod 2"])
+        );
+
+        let record = StringRecord::from(vec!["1,500000", "-2,5E-3", "12", "valve open, rod 2"]);
+        let normalized = header.file.normalize(record);
+        assert_eq!(
+            normalized,
+            StringRecord::from(vec!["1.500000", "-2.5E-3", "12", "valve open, rod 2"])
+        );
+    }
+}
+
+#[cfg(test)]
+mod graph_tests {
+    use crate::channels::ChannelMap;
+    use crate::graph::{build_records, GraphRecord, CHANNEL_METATYPE};
+    use crate::lvm::{LvmHeader, LvmSegmentHeader};
+    use crate::ISUFile;
+    use std::path::Path;
+
+    #[test]
+    fn build_records_test() {
+        let db_file = ISUFile {
+            path: String::from("./Feb_13_2023_14_29/Most Engineering Data.txt"),
+            last_position_read: 0,
+            last_index: 0,
+            headers: String::from("X_Value,Ch1_CPS,CCR_cm,DateTime"),
+            time: String::from("2023-02-13 14:29:00"),
+            lvm_header: Some(LvmHeader {
+                segment: LvmSegmentHeader {
+                    channels: 2,
+                    y_unit_label: vec![String::from("CPS"), String::from("cm")],
+                    delta_x: vec![1.0, 1.0],
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            identity: None,
+            accepted_rows: 0,
+            rejected_rows: 0,
+            window: None,
+            sequence: 0,
         };
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
+        let channels = ChannelMap::load(None).unwrap();
+        let records = build_records(
+            "Feb_13_2023_14_29",
+            &db_file,
+            Path::new("out.csv"),
+            &channels,
+        );
+
+        let channels: Vec<&GraphRecord> = records
+            .iter()
+            .filter(|r| matches!(r, GraphRecord::Node { metatype, .. } if metatype == CHANNEL_METATYPE))
+            .collect();
+        assert_eq!(channels.len(), 2);
+
+        match channels[1] {
+            GraphRecord::Node { id, properties, .. } => {
+                assert_eq!(id, "channel:CCR_cm");
+                assert_eq!(properties["column"], "CCR_cm");
+                assert_eq!(properties["unit"], "cm");
             }
+            GraphRecord::Edge { .. } => panic!("expected a node"),
+        }
+
+        // the generated file must be tied back to both its run and its source file
+        assert!(records.contains(&GraphRecord::Edge {
+            relationship: String::from("produced_by"),
+            origin_id: String::from("timeseries:out.csv"),
+            destination_id: String::from("run:Feb_13_2023_14_29"),
+        }));
+        assert!(records.contains(&GraphRecord::Edge {
+            relationship: String::from("derived_from"),
+            origin_id: String::from("timeseries:out.csv"),
+            destination_id: String::from("file:./Feb_13_2023_14_29/Most Engineering Data.txt"),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod timestamps_tests {
+    use crate::lvm::{LvmHeader, LvmSegmentHeader};
+    use crate::timestamps::{
+        format_time, parse_stored_time, parse_timezone, run_start, sample_time,
+    };
+    use chrono_tz::Tz;
+
+    #[test]
+    fn run_start_timezone_test() {
+        let tz = parse_timezone("America/Boise").unwrap();
+
+        // MST is UTC-7
+        let start = run_start("Feb_13_2023_14_29", tz).unwrap();
+        assert_eq!(format_time(Some(start)), "2023-02-13T21:29:00+00:00");
+
+        // MDT is UTC-6
+        let start = run_start("Jul_13_2023_14_29", tz).unwrap();
+        assert_eq!(format_time(Some(start)), "2023-07-13T20:29:00+00:00");
+
+        // 02:30 never happened on the night clocks sprang forward, it's read as 03:30 MDT
+        let start = run_start("Mar_12_2023_02_30", tz).unwrap();
+        assert_eq!(format_time(Some(start)), "2023-03-12T09:30:00+00:00");
+
+        // 01:30 happened twice on the night clocks fell back, the first (MDT) one is used
+        let start = run_start("Nov_05_2023_01_30", tz).unwrap();
+        assert_eq!(format_time(Some(start)), "2023-11-05T07:30:00+00:00");
+
+        // rows stored before the plugin was timezone aware are read in the DAS timezone
+        let stored = parse_stored_time("2023-02-13 14:29:00", tz).unwrap();
+        assert_eq!(format_time(Some(stored)), "2023-02-13T21:29:00+00:00");
+        let stored = parse_stored_time("2023-02-13T21:29:00+00:00", tz).unwrap();
+        assert_eq!(format_time(Some(stored)), "2023-02-13T21:29:00+00:00");
+
+        assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn sample_time_test() {
+        let start = run_start("Feb_13_2023_14_29", Tz::UTC).unwrap();
+
+        // relative X values are offsets from the run start
+        let t = sample_time(start, Some("90.5"), None, 0);
+        assert_eq!(format_time(t), "2023-02-13T14:30:30.500+00:00");
+
+        // without an X value we fall back to the segment's X0 and Delta_X
+        let header = LvmHeader {
+            segment: LvmSegmentHeader {
+                x0: vec![10.0],
+                delta_x: vec![0.25],
+                ..Default::default()
+            },
+            ..Default::default()
         };
+        let t = sample_time(start, Some(""), Some(&header), 4);
+        assert_eq!(format_time(t), "2023-02-13T14:29:11+00:00");
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+        // LabVIEW absolute times count seconds from 1904
+        let t = sample_time(start, Some("3759143340"), None, 0);
+        assert_eq!(format_time(t), "2023-02-13T14:29:00+00:00");
+
+        assert_eq!(sample_time(start, Some("NaN"), None, 0), None);
+        assert_eq!(sample_time(start, None, None, 0), None);
+    }
+}
 
-        std::fs::copy(
-            "./test_data/Feb_13_2023_14_29/Most Engineering Data.txt",
-            "./test_data/Feb_13_2023_14_29/tail.txt",
+#[cfg(test)]
+mod classify_tests {
+    use crate::classify::{FileKind, RuleConfig, Rules, DEFAULT_RULES};
+    use std::path::Path;
+
+    #[test]
+    fn default_rules_test() {
+        let rules =
+            Rules::new(&serde_json::from_str::<Vec<RuleConfig>>(DEFAULT_RULES).unwrap()).unwrap();
+
+        for (file, kind) in [
+            ("Most Engineering Data.txt", FileKind::Engineering),
+            ("Ch 3 Engineering Data.txt", FileKind::Engineering),
+            ("Temperature.txt", FileKind::Temperature),
+            ("Digitals.txt", FileKind::Digitals),
+            ("Reduced Raw Data.txt", FileKind::Reduced),
+            ("Events.txt", FileKind::Events),
+            ("Most Engineering Data.txt.tmp", FileKind::Ignore),
+        ] {
+            let path = Path::new("./test_data/Feb_13_2023_14_29").join(file);
+            assert_eq!(rules.classify(&path).unwrap(), kind, "{file}");
+        }
+
+        assert!(rules
+            .classify(Path::new("./test_data/Feb_13_2023_14_29/notes.docx"))
+            .is_err());
+    }
+
+    #[test]
+    fn directory_rules_test() {
+        let rules = Rules::new(
+            &serde_json::from_str::<Vec<RuleConfig>>(
+                r#"[
+                    {"kind": "ignore", "directory": {"regex": "/archive$"}},
+                    {"kind": "engineering", "file_name": {"glob": "*.txt"}}
+                ]"#,
+            )
+            .unwrap(),
         )
         .unwrap();
 
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/tail.txt").unwrap(),
-            db.clone(),
-            Some(ts_tx.clone()),
-            Some(g_tx.clone()),
+        assert_eq!(
+            rules.classify(Path::new("/das/archive/Data.txt")).unwrap(),
+            FileKind::Ignore
+        );
+        assert_eq!(
+            rules.classify(Path::new("/das/Feb_13_2023_14_29/Data.txt")).unwrap(),
+            FileKind::Engineering
         );
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+        // a rule must match on something
+        assert!(Rules::new(
+            &serde_json::from_str::<Vec<RuleConfig>>(r#"[{"kind": "events"}]"#).unwrap()
+        )
+        .is_err());
+    }
+}
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        let mut message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
-            }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
-            }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
+#[cfg(test)]
+mod events_tests {
+    use crate::events::{Event, EventCategory};
+    use crate::timestamps::{format_time, parse_timezone, run_start};
+
+    #[test]
+    fn parse_event_test() {
+        let tz = parse_timezone("America/Boise").unwrap();
+        let start = run_start("Feb_13_2023_14_29", tz).unwrap();
+
+        let event = Event::parse("2/13/2023 2:31:05 PM,Coarse Rod 1 inserted,\r\n", start, tz).unwrap();
+        assert_eq!(format_time(event.time), "2023-02-13T21:31:05+00:00");
+        assert_eq!(event.category, EventCategory::RodMotion);
+        assert_eq!(event.component.as_deref(), Some("Coarse Rod 1"));
+        assert_eq!(event.text, "Coarse Rod 1 inserted");
+        assert_eq!(event.raw, "2/13/2023 2:31:05 PM,Coarse Rod 1 inserted");
+
+        // times without a date that are earlier than the run start are past midnight
+        let event = Event::parse("00:10:00\tSCRAM - Ch 2 high flux\n", start, tz).unwrap();
+        assert_eq!(format_time(event.time), "2023-02-14T07:10:00+00:00");
+        assert_eq!(event.category, EventCategory::Scram);
+        assert_eq!(event.component.as_deref(), Some("Ch 2"));
+
+        let event = Event::parse("Operator note: reactor in steady state\n", start, tz).unwrap();
+        assert_eq!(event.time, None);
+        assert_eq!(event.category, EventCategory::OperatorNote);
+        assert_eq!(event.text, "Operator note: reactor in steady state");
+
+        let event = Event::parse("14:45:00,Mode changed to Auto", start, tz).unwrap();
+        assert_eq!(event.category, EventCategory::ModeChange);
+
+        // short and blank lines used to panic when the line ending was chopped off
+        let event = Event::parse("x\n", start, tz).unwrap();
+        assert_eq!(event.category, EventCategory::Other);
+        assert!(Event::parse("\r\n", start, tz).is_none());
+        assert!(Event::parse("", start, tz).is_none());
+    }
+
+    #[test]
+    fn event_category_test() {
+        let tz = parse_timezone("America/Boise").unwrap();
+        let start = run_start("Feb_13_2023_14_29", tz).unwrap();
+        let category = |text: &str| Event::parse(text, start, tz).unwrap().category;
+
+        assert_eq!(category("Reactor tripped on high flux"), EventCategory::Scram);
+        assert_eq!(category("Fine rod moved up 2 cm"), EventCategory::RodMotion);
+        assert_eq!(category("Safety Rod 2 withdrawn"), EventCategory::RodMotion);
+        assert_eq!(category("High flux alarms cleared"), EventCategory::Alarm);
+
+        // keywords only count as whole words, and a planned shutdown isn't a scram
+        assert_eq!(category("Reactor shutdown complete"), EventCategory::Other);
+        assert_eq!(category("Insulation stripped on cable 3"), EventCategory::Other);
+        assert_eq!(category("Rod startup checks done"), EventCategory::Other);
+        assert_eq!(category("Rod breakdown inspection"), EventCategory::Other);
+        assert_eq!(category("Model number recorded"), EventCategory::Other);
+    }
+}
+
+#[cfg(test)]
+mod tail_tests {
+    use crate::aggregate::Aggregation;
+    use crate::channels::ChannelMap;
+    use crate::format::{ChunkLimits, OutputFormat};
+    use crate::identity::FileIdentity;
+    use crate::journal::{part_path, Channel, Output};
+    use crate::validate::Validator;
+    use crate::{
+        create_tables, fetch_current, initial_event_process, initial_process, tail_event_process,
+        save_pass, tail_process, OutputOptions,
+    };
+    use chrono_tz::Tz;
+    use rusqlite::Connection;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use uuid::Uuid;
+
+    pub const LVM_HEADER: &str = "LabVIEW Measurement,\r
+Separator,Comma\r
+Decimal_Separator,.\r
+***End_of_Header***\r
+,\r
+Channels,2,\r
+Samples,1,1,\r
+X0,0.0000000000000000E+0,0.0000000000000000E+0,\r
+Delta_X,1.000000,1.000000,\r
+***End_of_Header***\r
+X_Value,Ch1_CPS,CCR_cm,Comment\r
+";
+
+    // chunk sizes the simulated DAS flushes in, none of them line up with line endings
+    const CHUNKS: [usize; 6] = [1, 3, 7, 13, 2, 64];
+
+    pub fn run_directory() -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(Uuid::new_v4().to_string())
+            .join("Feb_13_2023_14_29");
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // process runs a single plugin pass over the file and returns the rows it emitted, the output is
+    // read from its part file as nothing is published outside of the journal
+    pub fn process(path: &Path, db: &Connection, events: bool) -> Vec<csv::StringRecord> {
+        process_outputs(path, db, events).concat()
+    }
+
+    // process_outputs is process keeping the rows of each output apart
+    pub fn process_outputs(
+        path: &Path,
+        db: &Connection,
+        events: bool,
+    ) -> Vec<Vec<csv::StringRecord>> {
+        process_channels(path, db, events, None)
+            .into_iter()
+            .filter(|(channel, _)| *channel == Channel::Timeseries)
+            .map(|(_, rows)| rows)
+            .collect()
+    }
+
+    // process_channels is process_outputs including quarantined rows, with the channel of each
+    // output. Manifests are left out
+    pub fn process_channels(
+        path: &Path,
+        db: &Connection,
+        events: bool,
+        aggregation: Option<&Aggregation>,
+    ) -> Vec<(Channel, Vec<csv::StringRecord>)> {
+        let validator = Validator::load(None).unwrap();
+        let channels = ChannelMap::load(None).unwrap();
+        let options = OutputOptions {
+            aggregation,
+            ..csv_options(&validator, &channels)
+        };
+
+        process_with(path, db, events, &options)
+            .iter()
+            .filter(|o| o.channel != Channel::Manifest)
+            .map(|o| (o.channel, read_rows(&part_path(&o.path))))
+            .collect()
+    }
+
+    // process_with runs one pass over a file with the given options, returning its outputs
+    pub fn process_with(
+        path: &Path,
+        db: &Connection,
+        events: bool,
+        options: &OutputOptions,
+    ) -> Vec<Output> {
+        let new_output = |_| output_path(path);
+        let pass = match (fetch_current(path, db).unwrap(), events) {
+            (None, false) => initial_process(path.to_path_buf(), &new_output, options, 0, Tz::UTC),
+            (Some(f), false) => tail_process(f, path.to_path_buf(), &new_output, options, Tz::UTC),
+            (None, true) => initial_event_process(path.to_path_buf(), &new_output, options, 0, Tz::UTC),
+            (Some(f), true) => {
+                tail_event_process(f, path.to_path_buf(), &new_output, options, Tz::UTC)
             }
         };
+        save_pass(path, pass.unwrap(), db).unwrap()
+    }
 
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
+    // csv_options writes plain csv without aggregation or chunking
+    pub fn csv_options<'a>(validator: &'a Validator, channels: &'a ChannelMap) -> OutputOptions<'a> {
+        OutputOptions {
+            validator,
+            channels,
+            aggregation: None,
+            format: OutputFormat::Csv,
+            limits: ChunkLimits::default(),
+            stop_index: None,
+            manifests: true,
+        }
+    }
 
-        let comparison = File::open(Path::new("./test_data/EngineeringResults.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+    // output_path names an output in the run directory so nothing is left in the working directory
+    pub fn output_path(path: &Path) -> PathBuf {
+        path.with_file_name(format!("{}.csv", Uuid::new_v4()))
+    }
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+    // read_rows reads and removes a csv output
+    pub fn read_rows(output: &Path) -> Vec<csv::StringRecord> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_path(output)
+            .unwrap();
+        let rows = reader.records().map(|r| r.unwrap()).collect();
+        fs::remove_file(output).unwrap();
+        rows
+    }
 
-        // now we're testing the tailing
-        let file = OpenOptions::new()
+    // flush writes data to the file in uneven chunks, running a plugin pass after each one
+    fn flush(path: &Path, db: &Connection, data: &str, events: bool) -> Vec<csv::StringRecord> {
+        let mut file = OpenOptions::new()
+            .create(true)
             .append(true)
-            .open("./test_data/Feb_13_2023_14_29/tail.txt")
+            .open(path)
             .unwrap();
 
-        // write a new record
-        let mut csv_writer = csv::WriterBuilder::new().flexible(true).from_writer(file);
-        csv_writer
-            .write_record([
-                "",
-                "0.000000",
-                "7123.633812",
-                "9.472421E-10",
-                "0.054466",
-                "0.000000",
-                "0.000000",
-                "0.025790",
-                "24.506584",
-                "18.462020",
-            ])
-            .unwrap();
+        let mut rows = vec![];
+        let mut data = data.as_bytes();
+        for size in CHUNKS.iter().cycle() {
+            if data.is_empty() {
+                break;
+            }
+
+            let (chunk, rest) = data.split_at((*size).min(data.len()));
+            file.write_all(chunk).unwrap();
+            file.flush().unwrap();
+            rows.extend(process(path, db, events));
+            data = rest;
+        }
 
-        csv_writer.flush().unwrap();
+        rows
+    }
 
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/tail.txt").unwrap(),
-            db,
-            Some(ts_tx),
-            Some(g_tx),
+    #[test]
+    fn engineering_partial_line_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        // the header is written before the plugin first sees the file
+        fs::write(&path, LVM_HEADER).unwrap();
+        let mut data = String::new();
+        for i in 0..40 {
+            data.push_str(format!("{i}.000000,{}.250000,{i},\r\n", i * 10).as_str());
+        }
+
+        let rows = flush(&path, &db, data.as_str(), false);
+        assert_eq!(rows.len(), 40);
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(&row[0], format!("{i}.000000").as_str());
+            assert_eq!(&row[1], format!("{}.250000", i * 10).as_str());
+            assert_eq!(&row[2], format!("{i}").as_str());
+        }
+        assert_eq!(&rows[39][5], "2023-02-13T14:29:39+00:00");
+
+        // a fragment left at the end of the file is held back until its line is complete
+        let rows = flush(&path, &db, "40.000000,400.25", false);
+        assert!(rows.is_empty());
+        let rows = flush(&path, &db, "0000,40,\r\n", false);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][1], "400.250000");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn partial_file_header_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        // nothing is kept for a file seen in the middle of its header, up to its column row
+        let (first, rest) = LVM_HEADER.split_at(LVM_HEADER.find("X_Value").unwrap() + 10);
+        assert!(flush(&path, &db, first, false).is_empty());
+        assert!(fetch_current(&path, &db).unwrap().is_none());
+
+        let mut data = String::from(rest);
+        for i in 0..5 {
+            data.push_str(format!("{i}.000000,{}.250000,{i},\r\n", i * 10).as_str());
+        }
+        let rows = flush(&path, &db, data.as_str(), false);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(&rows[4][1], "40.250000");
+        let db_file = fetch_current(&path, &db).unwrap().unwrap();
+        assert_eq!(db_file.headers, "X_Value,Ch1_CPS,CCR_cm,Comment,DateTime,Timestamp");
+        assert_eq!(db_file.last_index, 5);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn tab_separated_decimal_comma_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        let header = LVM_HEADER
+            .replace(',', "\t")
+            .replace("Separator\tComma", "Separator\tTab")
+            .replace("Decimal_Separator\t.", "Decimal_Separator\t,")
+            .replace("1.000000", "0,500000");
+        fs::write(&path, header).unwrap();
+
+        let rows = flush(
+            &path,
+            &db,
+            "0,000000\t10,250000\t1,5\tvalve open, rod 2\r\n0,500000\t11,000000\t2\t\r\n",
+            false,
         );
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][0], "0.000000");
+        assert_eq!(&rows[0][1], "10.250000");
+        assert_eq!(&rows[0][2], "1.5");
+        assert_eq!(&rows[1][5], "2023-02-13T14:29:00.500+00:00");
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    pub const SEGMENT_HEADER: &str = "Channels,3,\r
+Samples,1,1,1,\r
+X0,0.0000000000000000E+0,0.0000000000000000E+0,0.0000000000000000E+0,\r
+Delta_X,1.000000,1.000000,1.000000,\r
+***End_of_Header***\r
+X_Value,Ch1_CPS,CCR_cm,FCR_cm,Comment\r
+";
+
+    #[test]
+    fn segment_schema_change_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        // a second segment with the same columns carries on in the same output
+        let same_columns = LVM_HEADER.split_once("***End_of_Header***\r\n").unwrap().1;
+        fs::write(
+            &path,
+            format!("{LVM_HEADER}0.0,1.0,2,\r\n{same_columns}1.0,1.5,3,\r\n"),
+        )
+        .unwrap();
+        let outputs = process_outputs(&path, &db, false);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].len(), 2);
+
+        // a segment adding a channel starts a new output under the new headers
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "2.0,2.5,4,\r\n{SEGMENT_HEADER}3.0,3.5,5,6,\r\n").unwrap();
+        let outputs = process_outputs(&path, &db, false);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].len(), 1);
+        assert_eq!(&outputs[0][0][0], "2.0");
+        assert_eq!(outputs[1].len(), 1);
+        assert_eq!(&outputs[1][0][3], "6");
+
+        let db_file = fetch_current(&path, &db).unwrap().unwrap();
+        assert_eq!(db_file.headers, "X_Value,Ch1_CPS,CCR_cm,FCR_cm,Comment,DateTime,Timestamp");
+        assert_eq!(db_file.lvm_header.unwrap().segment.channels, 3);
+        assert_eq!(db_file.last_index, 4);
+
+        // later rows keep to the new headers
+        let outputs = flush(&path, &db, "4.0,4.5,6,7,\r\n", false);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(&outputs[0][3], "7");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn partial_segment_header_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        fs::write(&path, format!("{LVM_HEADER}0.0,1.0,2,\r\n")).unwrap();
+        assert_eq!(process(&path, &db, false).len(), 1);
+
+        // the segment header is only acted on once it and its column row are complete
+        let (first, rest) = SEGMENT_HEADER.split_at(40);
+        let outputs = flush(&path, &db, format!("1.0,1.5,3,\r\n{first}").as_str(), false);
+        assert_eq!(outputs.len(), 1);
+        let db_file = fetch_current(&path, &db).unwrap().unwrap();
+        assert_eq!(db_file.headers, "X_Value,Ch1_CPS,CCR_cm,Comment,DateTime,Timestamp");
+
+        let outputs = flush(&path, &db, format!("{rest}2.0,2.5,4,5,\r\n").as_str(), false);
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(&outputs[0][3], "5");
+        let db_file = fetch_current(&path, &db).unwrap().unwrap();
+        assert_eq!(db_file.headers, "X_Value,Ch1_CPS,CCR_cm,FCR_cm,Comment,DateTime,Timestamp");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn events_partial_line_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        let mut data = String::new();
+        for i in 0..25 {
+            data.push_str(format!("14:{:02}:00,Operator note {i}\r\n", 30 + i).as_str());
+        }
+
+        let rows = flush(&path, &db, data.as_str(), true);
+        assert_eq!(rows.len(), 25);
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(&row[1], format!("{i}").as_str());
+            assert_eq!(&row[6], format!("Operator note {i}").as_str());
+            assert_eq!(&row[7], "Feb_13_2023_14_29");
+        }
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn events_invalid_utf8_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        // a note typed in latin-1 doesn't stop the events after it from being read
+        let note = |i: usize| [format!("14:{:02}:00,Reactor at 20", 30 + i).as_bytes(), b"\xb0C\r\n"].concat();
+        fs::write(&path, [events(0, 2).into_bytes(), note(2), events(3, 5).into_bytes()].concat()).unwrap();
+        let rows = process(&path, &db, true);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(&rows[2][6], "Reactor at 20\u{fffd}C");
+        assert_eq!(&rows[4][6], "Operator note 4");
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[note(5), events(6, 8).into_bytes()].concat()).unwrap();
+        let rows = process(&path, &db, true);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(&rows[0][1], "5");
+        assert_eq!(&rows[2][6], "Operator note 7");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
+    pub fn events(from: usize, to: usize) -> String {
+        (from..to)
+            .map(|i| format!("14:{:02}:00,Operator note {i}\r\n", 30 + i))
+            .collect()
+    }
+
+    #[test]
+    fn identity_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        fs::write(&path, events(0, 5)).unwrap();
+
+        let identity = FileIdentity::read(&path).unwrap();
+        assert_eq!(identity.changed(&path).unwrap(), None);
+
+        // appending keeps the identity, rewriting the start of the file does not
+        fs::write(&path, events(0, 6)).unwrap();
+        assert_eq!(identity.changed(&path).unwrap(), None);
+        fs::write(&path, events(1, 7)).unwrap();
+        assert!(identity.changed(&path).unwrap().is_some());
+
+        // a shorter file was truncated
+        let identity = FileIdentity::read(&path).unwrap();
+        fs::write(&path, events(1, 3)).unwrap();
+        assert!(identity.changed(&path).unwrap().is_some());
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn truncated_file_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        assert_eq!(flush(&path, &db, events(0, 10).as_str(), true).len(), 10);
+
+        // the DAS truncates the file and starts writing again, everything is read from the start
+        fs::write(&path, "").unwrap();
+        let rows = flush(&path, &db, events(20, 23).as_str(), true);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(&rows[0][1], "0");
+        assert_eq!(&rows[0][6], "Operator note 20");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn replaced_file_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        assert_eq!(flush(&path, &db, events(0, 10).as_str(), true).len(), 10);
+
+        // a new file of the same length is renamed over the old one
+        let replacement = dir.join("Events.new");
+        fs::write(&replacement, events(10, 20)).unwrap();
+        fs::rename(&replacement, &path).unwrap();
+
+        let rows = flush(&path, &db, events(20, 21).as_str(), true);
+        assert_eq!(rows.len(), 11);
+        assert_eq!(&rows[0][6], "Operator note 10");
+        assert_eq!(&rows[10][6], "Operator note 20");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use crate::channels::ChannelMap;
+    use crate::config::{CleanupPolicy, Configuration};
+    use crate::journal::{self, part_path, Channel, Destination, Output};
+    use crate::tests::tail_tests::{csv_options, events, output_path, read_rows, run_directory};
+    use crate::validate::Validator;
+    use crate::{create_tables, fetch_current, initial_event_process, save_pass, ISUProcessor};
+    use chrono_tz::Tz;
+    use jester_core::DataSourceMessage;
+    use rusqlite::Connection;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+    use uuid::Uuid;
+
+    pub fn received(rx: &mut UnboundedReceiver<DataSourceMessage>) -> Vec<PathBuf> {
+        let mut files = vec![];
+        while let Ok(message) = rx.try_recv() {
+            if let DataSourceMessage::File((path, _)) = message {
+                files.push(path);
             }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
+        }
+        files
+    }
+
+    fn destination(chan: &UnboundedSender<DataSourceMessage>) -> Destination<'_> {
+        Destination {
+            timeseries: chan,
+            graph: None,
+            cleanup: CleanupPolicy::Delete,
+        }
+    }
+
+    #[test]
+    fn uncommitted_output_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        fs::write(&path, events(0, 5)).unwrap();
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let (validator, channels) = (Validator::load(None).unwrap(), ChannelMap::load(None).unwrap());
+        let options = csv_options(&validator, &channels);
+
+        // the plugin stops after writing the output but before committing, nothing is remembered
+        let tx = db.unchecked_transaction().unwrap();
+        let output = save_pass(&path, initial_event_process(path.clone(), &|_| output_path(&path), &options, 0, Tz::UTC).unwrap(), &tx).unwrap()[0].path.clone();
+        drop(tx);
+        assert!(fetch_current(&path, &db).unwrap().is_none());
+        assert!(!output.exists());
+        fs::remove_file(part_path(&output)).unwrap();
+
+        // so the rows are read again, once
+        let tx = db.unchecked_transaction().unwrap();
+        let output = save_pass(&path, initial_event_process(path.clone(), &|_| output_path(&path), &options, 0, Tz::UTC).unwrap(), &tx).unwrap()[0].path.clone();
+        tx.commit().unwrap();
+        assert_eq!(read_rows(&part_path(&output)).len(), 5);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn recover_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        fs::write(&path, events(0, 5)).unwrap();
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let (tx_chan, mut rx) = unbounded_channel();
+        let (validator, channels) = (Validator::load(None).unwrap(), ChannelMap::load(None).unwrap());
+        let options = csv_options(&validator, &channels);
+
+        // committed but the plugin stopped before delivering
+        let tx = db.unchecked_transaction().unwrap();
+        let output = save_pass(&path, initial_event_process(path.clone(), &|_| output_path(&path), &options, 0, Tz::UTC).unwrap(), &tx).unwrap()[0].path.clone();
+        let outputs = [Output { path: output.clone(), channel: Channel::Timeseries }];
+        journal::record(&tx, path.to_str().unwrap(), &outputs).unwrap();
+        tx.commit().unwrap();
+
+        journal::recover(&db, true, &destination(&tx_chan)).unwrap();
+        assert_eq!(received(&mut rx), vec![output.clone()]);
+        assert!(output.exists());
+
+        // sent outputs are only resent after a restart
+        journal::recover(&db, false, &destination(&tx_chan)).unwrap();
+        assert!(received(&mut rx).is_empty());
+
+        // once Jester has uploaded and removed the file it's dropped from the journal
+        assert_eq!(read_rows(&output).len(), 5);
+        journal::recover(&db, true, &destination(&tx_chan)).unwrap();
+        assert!(received(&mut rx).is_empty());
+        let pending: i64 = db
+            .query_row("SELECT COUNT(*) FROM isu_pending_output", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pending, 0);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn recover_without_graph_test() {
+        let dir = run_directory();
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let (tx_chan, mut rx) = unbounded_channel();
+
+        // a graph record committed before a restart that left Jester without a graph channel
+        let graph = dir.join(format!("{}.json", Uuid::new_v4()));
+        fs::write(part_path(&graph), "[]").unwrap();
+        journal::record(&db, "Events.txt", &[Output { path: graph.clone(), channel: Channel::Graph }]).unwrap();
+
+        // there's nowhere to send it, so it's removed rather than left in the output directory
+        journal::recover(&db, true, &destination(&tx_chan)).unwrap();
+        assert!(received(&mut rx).is_empty());
+        assert!(!graph.exists() && !part_path(&graph).exists());
+        journal::recover(&db, true, &destination(&tx_chan)).unwrap();
+        let pending: i64 = db
+            .query_row("SELECT COUNT(*) FROM isu_pending_output", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pending, 0);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn remove_orphans_test() {
+        let dir = run_directory();
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        let orphan = part_path(&dir.join(format!("{}.csv", Uuid::new_v4())));
+        let journaled = dir.join(format!("{}.csv", Uuid::new_v4()));
+        let unrelated = dir.join("notes.txt.part");
+        for p in [&orphan, &part_path(&journaled), &unrelated] {
+            fs::write(p, "").unwrap();
+        }
+        journal::record(
+            &db,
+            "Events.txt",
+            &[Output { path: journaled.clone(), channel: Channel::Timeseries }],
+        )
+        .unwrap();
+
+        journal::remove_orphans(&db, &dir).unwrap();
+        assert!(!orphan.exists());
+        assert!(part_path(&journaled).exists());
+        assert!(unrelated.exists());
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn process_file_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        let processor = ISUProcessor::with_config(Configuration {
+            state_db: PathBuf::from(":memory:"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
+        })
+        .unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, mut ts_rx) = unbounded_channel();
+        let (graph_chan, mut graph_rx) = unbounded_channel();
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path).unwrap();
+        let mut rows = vec![];
+        for i in 0..3 {
+            file.write_all(events(i * 4, i * 4 + 4).as_bytes()).unwrap();
+            processor
+                .process_file(path.clone(), Some(ts_chan.clone()), Some(graph_chan.clone()))
+                .unwrap();
+
+            for output in received(&mut ts_rx) {
+                rows.extend(read_rows(&output));
             }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
+            for output in received(&mut graph_rx) {
+                fs::remove_file(output).unwrap();
             }
-        };
+        }
 
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
+        // every row is delivered exactly once and nothing is left pending
+        assert_eq!(rows.len(), 12);
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(&row[1], format!("{i}").as_str());
+        }
+        processor.process_file(path.clone(), Some(ts_chan), None).unwrap();
+        assert!(received(&mut ts_rx).iter().all(|o| read_rows(o).is_empty()));
 
-        let comparison = File::open(Path::new("./test_data/EngineeringResultsTail.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+#[cfg(test)]
+mod config_tests {
+    use crate::classify::FileKind;
+    use crate::config::{contains_uuid, CleanupPolicy, Configuration};
+    use crate::journal::{self, Channel, Destination, Output};
+    use crate::tests::journal_tests::received;
+    use crate::tests::tail_tests::run_directory;
+    use crate::create_tables;
+    use chrono::{TimeZone, Utc};
+    use jester_core::DataSourceMessage;
+    use rusqlite::Connection;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use tokio::sync::mpsc::unbounded_channel;
+    use uuid::Uuid;
+
+    #[test]
+    fn from_file_test() {
+        let dir = run_directory();
+
+        // the plugin's section of a Jester configuration file
+        let jester = dir.join("jester.yml");
+        fs::write(
+            &jester,
+            "data_sources: []
+isu_plugin:
+  state_db: /data/agn201_plugin
+  output_dir: /data/outputs
+  output_name: \"{run_time}_{kind}_{uuid}\"
+  cleanup: retain
+  retention_days: 3
+",
+        )
+        .unwrap();
+        let config = Configuration::from_file(&jester).unwrap();
+        assert_eq!(config.state_db, PathBuf::from("/data/agn201_plugin"));
+        assert_eq!(config.output_dir, PathBuf::from("/data/outputs"));
+        assert_eq!(config.cleanup, CleanupPolicy::Retain);
+        assert_eq!(config.retention_days, 3);
+        assert_eq!(config.das_timezone, None);
+
+        // a file of its own, anything not set keeps its default
+        let own = dir.join("isu.yml");
+        fs::write(&own, "das_timezone: America/Boise\n").unwrap();
+        let config = Configuration::from_file(&own).unwrap();
+        assert_eq!(config.das_timezone.as_deref(), Some("America/Boise"));
+        assert_eq!(config.state_db, Configuration::default().state_db);
+        assert_eq!(config.cleanup, CleanupPolicy::Delete);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
     }
 
-    #[tokio::test]
-    async fn save_file_test() {
-        fs::remove_file(".test.db").await.unwrap();
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
+    #[test]
+    fn output_path_test() {
+        let config = Configuration {
+            output_dir: PathBuf::from("/data/outputs"),
+            output_name: String::from("{run}_{run_time}_{kind}_{source}_{uuid}"),
+            ..Configuration::default()
         };
+        config.validate().unwrap();
+
+        let run_start = Utc.with_ymd_and_hms(2023, 2, 13, 21, 29, 0).unwrap();
+        let source = Path::new("/das/Feb_13_2023_14_29/Most Engineering Data.txt");
+        let output = config.output_path(FileKind::Engineering, run_start, source, 0, "csv");
+
+        assert_eq!(output.parent(), Some(Path::new("/data/outputs")));
+        let name = output.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(
+            "Feb_13_2023_14_29_20230213T212900Z_engineering_Most Engineering Data_"
+        ));
+        assert!(name.ends_with(".csv"));
+        assert!(contains_uuid(name));
+        assert_ne!(output, config.output_path(FileKind::Engineering, run_start, source, 0, "csv"));
+
+        // names have to stay unique and inside the output directory
+        for name in ["{run_time}_{kind}", "runs/{uuid}"] {
+            let config = Configuration {
+                output_name: String::from(name),
+                ..Configuration::default()
+            };
+            assert!(config.validate().is_err());
+        }
+    }
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
+    #[test]
+    fn keep_policy_test() {
+        let dir = run_directory();
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let (chan, mut rx) = unbounded_channel();
+        let destination = Destination {
+            timeseries: &chan,
+            graph: None,
+            cleanup: CleanupPolicy::Keep,
         };
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
+        let output = dir.join(format!("{}.csv", Uuid::new_v4()));
+        fs::write(journal::part_path(&output), "").unwrap();
+        let outputs = [Output { path: output.clone(), channel: Channel::Timeseries }];
+        journal::record(&db, "Events.txt", &outputs).unwrap();
+        journal::deliver(&db, &outputs, &destination).unwrap();
+
+        // Jester is told to keep the file, so it can't be resent after a restart
+        match rx.try_recv().unwrap() {
+            DataSourceMessage::File((path, delete)) => {
+                assert_eq!(path, output);
+                assert!(!delete);
             }
-        };
+            _ => panic!("expected a file message"),
+        }
+        journal::recover(&db, true, &destination).unwrap();
+        assert!(received(&mut rx).is_empty());
+        assert!(output.exists());
+
+        // retained outputs are removed once they're old enough, anything else is left alone
+        let unrelated = dir.join("notes.csv");
+        fs::write(&unrelated, "").unwrap();
+        journal::remove_expired(&db, &dir, 1).unwrap();
+        assert!(output.exists());
+        journal::remove_expired(&db, &dir, 0).unwrap();
+        assert!(!output.exists());
+        assert!(unrelated.exists());
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+#[cfg(test)]
+mod health_tests {
+    use crate::config::Configuration;
+    use crate::tests::tail_tests::run_directory;
+    use crate::{create_tables, health, ISUProcessor};
+    use rusqlite::{Connection, OpenFlags};
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn health_check_test() {
+        let dir = run_directory();
+        let path = dir.join("agn201_plugin");
+        let db = Connection::open(&path).unwrap();
+
+        // the schema has to be created before files are accepted
+        assert!(health::check(&db).is_err());
+        create_tables(&db).unwrap();
+        health::check(&db).unwrap();
+
+        // an isu table from before the plugin tracked indexes is out of date
+        let old = Connection::open_in_memory().unwrap();
+        create_tables(&old).unwrap();
+        old.execute_batch("DROP TABLE isu; CREATE TABLE isu (path text UNIQUE ON CONFLICT REPLACE, last_position_read integer, headers text, time text);")
+            .unwrap();
+        assert!(health::check(&old).is_err());
 
-        let result = save_file(
-            ISUFile {
-                path: String::from("test"),
-                last_position_read: 0,
-                last_index: 0,
-                headers: String::from("header1, header2"),
-                time: String::from(""),
-            },
-            db.clone(),
-        );
-        assert!(result.is_ok(), "{:?}", result.err());
+        let read_only = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+        assert!(health::check(&read_only).is_err());
 
-        // saving the same path should result in an ok as well, as it will upsert the existing record
-        let result = save_file(
-            ISUFile {
-                path: String::from("test"),
-                last_position_read: 0,
-                last_index: 0,
-                headers: String::from("header1, header2"),
-                time: String::from(""),
-            },
-            db,
-        );
-        assert!(result.is_ok(), "{:?}", result.err());
+        // another process holding the write lock
+        let other = Connection::open(&path).unwrap();
+        other.busy_timeout(Duration::ZERO).unwrap();
+        db.execute_batch("BEGIN IMMEDIATE").unwrap();
+        assert!(health::check(&other).is_err());
+        db.execute_batch("ROLLBACK").unwrap();
+        health::check(&other).unwrap();
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
     }
 
-    #[tokio::test]
-    async fn fetch_file_test() {
-        fs::remove_file(".test.db").await.unwrap();
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
-        };
+    #[test]
+    fn unavailable_state_db_test() {
+        let dir = run_directory();
+        fs::write(dir.join("file"), "").unwrap();
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
-        };
+        // a state database that can't be opened is an error, not a panic
+        let result = ISUProcessor::with_config(Configuration {
+            state_db: dir.join("file").join("agn201_plugin"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
+        });
+        assert!(result.is_err());
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
-            }
-        };
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+#[cfg(test)]
+mod migrations_tests {
+    use crate::migrations::{current_version, latest_version, migrate, MIGRATIONS};
+    use crate::{fetch_file, health};
+    use rusqlite::Connection;
+
+    // the isu table as the plugin created it before migrations existed
+    const LEGACY_SCHEMA: &str = "CREATE TABLE isu (path text UNIQUE ON CONFLICT REPLACE, last_position_read integer, headers text, time text, last_index integer);";
+
+    #[test]
+    fn migrate_legacy_test() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(LEGACY_SCHEMA).unwrap();
+        db.execute(
+            "INSERT INTO isu VALUES ('/das/Feb_13_2023_14_29/Events.txt', 4096, 'Event,Index,DateTime', '2023-02-13 14:29:00', 17)",
+            [],
+        )
+        .unwrap();
+        assert_eq!(current_version(&db).unwrap(), 0);
 
-        let result = save_file(
-            ISUFile {
-                path: String::from("test"),
-                last_position_read: 0,
-                last_index: 0,
-                headers: String::from("header1,header2"),
-                time: String::from(""),
-            },
-            db.clone(),
+        migrate(&db).unwrap();
+        assert_eq!(current_version(&db).unwrap(), latest_version());
+        health::check(&db).unwrap();
+
+        // the tail position survives the upgrade
+        let file = fetch_file("/das/Feb_13_2023_14_29/Events.txt", &db)
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.last_position_read, 4096);
+        assert_eq!(file.last_index, 17);
+
+        // every migration is recorded once, in order, and running again changes nothing
+        migrate(&db).unwrap();
+        let mut stmt = db
+            .prepare("SELECT version FROM isu_schema_version ORDER BY rowid")
+            .unwrap();
+        let versions: Vec<i64> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect();
+        let expected: Vec<i64> = MIGRATIONS.iter().map(|m| m.version).collect();
+        assert_eq!(versions, expected);
+    }
+
+    #[test]
+    fn migrate_without_last_index_test() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch("CREATE TABLE isu (path text UNIQUE ON CONFLICT REPLACE, last_position_read integer, headers text, time text);")
+            .unwrap();
+        db.execute(
+            "INSERT INTO isu VALUES ('/das/Feb_13_2023_14_29/Events.txt', 100, 'Event,Index,DateTime', '2023-02-13 14:29:00')",
+            [],
+        )
+        .unwrap();
+
+        migrate(&db).unwrap();
+        let file = fetch_file("/das/Feb_13_2023_14_29/Events.txt", &db)
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.last_position_read, 100);
+        assert_eq!(file.last_index, 0);
+    }
+
+    #[test]
+    fn newer_database_test() {
+        let db = Connection::open_in_memory().unwrap();
+        migrate(&db).unwrap();
+        db.execute(
+            "INSERT INTO isu_schema_version(version, description) VALUES (?1, 'from the future')",
+            [latest_version() + 1],
+        )
+        .unwrap();
+
+        assert!(migrate(&db).is_err());
+        assert!(health::check(&db).is_err());
+    }
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use crate::format::OutputFormat;
+    use crate::journal::Channel;
+    use crate::tests::tail_tests::{process_channels, run_directory};
+    use crate::validate::{quarantine_path, ChannelRule, ValueType, Validator};
+    use crate::{create_tables, fetch_current};
+    use rusqlite::Connection;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn column_rules_test() {
+        let validator = Validator::new(&[ChannelRule {
+            column: String::from("*_cm"),
+            value_type: Some(ValueType::Integer),
+            min: None,
+            max: Some(10.0),
+            required: false,
+        }])
+        .unwrap();
+        let checks = validator.columns(&[
+            String::from("X_Value"),
+            String::from("CCR_cm"),
+            String::from("FCR_cm"),
+        ]);
+
+        assert_eq!(checks.check(&csv::StringRecord::from(vec!["0.0", "2", ""])), None);
+        assert_eq!(
+            checks.check(&csv::StringRecord::from(vec!["0.0", "2.5", "11"])),
+            Some(String::from(
+                "CCR_cm value 2.5 is not an integer; FCR_cm value 11 is above 10"
+            ))
         );
-        assert!(result.is_ok(), "{:?}", result.err());
 
-        let result = fetch_file("test", db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
-        let result = result.unwrap();
-        assert!(result.is_some());
-        assert_eq!(result.unwrap().headers, String::from("header1,header2"));
+        assert_eq!(
+            quarantine_path(Path::new("out/output.csv"), OutputFormat::Csv),
+            Path::new("out/output_quarantine.csv")
+        );
+    }
 
-        let result = save_file(
-            ISUFile {
-                path: String::from("test"),
-                last_position_read: 0,
-                last_index: 0,
-                headers: String::from("header3,header4"),
-                time: String::from(""),
-            },
-            db.clone(),
+    #[test]
+    fn default_rules_test() {
+        let validator = Validator::load(None).unwrap();
+        let headers = ["X_Value", "Ch1_CPS", "Ch2_Watts", "Ch3_Watts", "CCR_cm", "FCR_cm", "Temp", "Comment"];
+        let checks = validator.columns(&headers.map(String::from));
+
+        let row = |values: [&str; 8]| csv::StringRecord::from(values.to_vec());
+        assert_eq!(checks.check(&row(["0.0", "7123.6", "9.4E-10", "0.05", "24.5", "18.4", "21.0", "startup"])), None);
+        assert_eq!(checks.check(&row(["0.0", "", "", "", "", "", "", ""])), None);
+        assert_eq!(
+            checks.check(&row(["0.0", "1", "NaN", "inf", "x", "1", "-inf", "NaN"])),
+            Some(String::from(
+                "Ch2_Watts value NaN is not a number; Ch3_Watts value inf is not a number; CCR_cm value x is not a number; Temp value -inf is not a number"
+            ))
         );
-        assert!(result.is_ok(), "{:?}", result.err());
+    }
 
-        let result = fetch_file("test", db);
-        assert!(result.is_ok(), "{:?}", result.err());
-        let result = result.unwrap();
-        assert!(result.is_some());
-        assert_eq!(result.unwrap().headers, String::from("header3,header4"))
+    #[test]
+    fn quarantine_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        fs::write(
+            &path,
+            "LabVIEW Measurement,\r
+Separator,Comma\r
+Decimal_Separator,.\r
+***End_of_Header***\r
+,\r
+Channels,2,\r
+Samples,1,1,\r
+X0,0.0000000000000000E+0,0.0000000000000000E+0,\r
+Delta_X,1.000000,1.000000,\r
+***End_of_Header***\r
+X_Value,Ch1_CPS,CCR_cm,Comment\r
+0.0,1.0,2,\r
+,1.5,3,\r
+2.0,NaN,4,\r
+3.0,-1.0,5,\r
+4.0,2.0,6,\r
+",
+        )
+        .unwrap();
+
+        let outputs = process_channels(&path, &db, false, None);
+        assert_eq!(outputs.len(), 2);
+        let (channel, rows) = &outputs[0];
+        assert_eq!(*channel, Channel::Timeseries);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[1][0], "4.0");
+
+        // rejected rows keep their index and run, the reason is in the last column
+        let (channel, rows) = &outputs[1];
+        assert_eq!(*channel, Channel::Quarantine);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(&rows[0][1], "1.5");
+        assert_eq!(&rows[0][6], "Feb_13_2023_14_29");
+        assert_eq!(&rows[0][7], "X_Value is required");
+        assert_eq!(&rows[1][7], "Ch1_CPS value NaN is not a number");
+        assert_eq!(&rows[2][7], "Ch1_CPS value -1.0 is below 0");
+
+        let db_file = fetch_current(&path, &db).unwrap().unwrap();
+        assert_eq!(db_file.accepted_rows, 2);
+        assert_eq!(db_file.rejected_rows, 3);
+        assert_eq!(db_file.last_index, 5);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
     }
+}
 
-    #[tokio::test]
-    async fn process_ch3_data_test() {
-        fs::remove_file(".test.db").await;
-        let (ts_tx, mut ts_rx) = unbounded_channel();
-        let (g_tx, mut g_rx) = unbounded_channel();
+#[cfg(test)]
+mod channels_tests {
+    use crate::channels::{snake_case, ChannelMap, ChannelMapping};
+    use crate::journal::{part_path, Channel};
+    use crate::tests::tail_tests::{csv_options, events, output_path, run_directory};
+    use crate::validate::Validator;
+    use crate::{create_tables, initial_event_process, initial_process, save_pass};
+    use chrono_tz::Tz;
+    use rusqlite::Connection;
+    use std::fs;
+
+    #[test]
+    fn snake_case_test() {
+        assert_eq!(snake_case("Ch1_CPS"), "ch1_cps");
+        assert_eq!(snake_case("Ch1 (CPS)"), "ch1_cps");
+        assert_eq!(snake_case(" Rod Position [cm] "), "rod_position_cm");
+        assert_eq!(snake_case("DateTime"), "date_time");
+        assert_eq!(snake_case("X_Value"), "x_value");
+    }
 
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
-        };
+    #[test]
+    fn output_headers_test() {
+        let channels = ChannelMap::new(&[ChannelMapping {
+            column: String::from("Ch1 (CPS)"),
+            name: Some(String::from("startup_cps")),
+            unit: Some(String::from("CPS")),
+        }])
+        .unwrap();
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
-        };
+        // unmapped columns are kept under their snake_case name, clashing names are numbered
+        let headers: Vec<String> = ["Ch1 (CPS)", "Ch2 (W)", "Ch2_W", "", "Timestamp"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        assert_eq!(
+            channels.output_headers(&headers),
+            vec!["startup_cps", "ch2_w", "ch2_w_2", "column_4", "timestamp"]
+        );
+        assert_eq!(channels.unit("Ch1 (CPS)"), Some("CPS"));
+        assert_eq!(channels.unit("Ch2 (W)"), None);
+
+        assert!(ChannelMap::new(&[ChannelMapping {
+            column: String::from("Ch1 (CPS)"),
+            name: Some(String::from("Ch1 CPS")),
+            unit: None,
+        }])
+        .is_err());
+        assert!(ChannelMap::new(&[ChannelMapping {
+            column: String::from("Ch1 (CPS)"),
+            name: None,
+            unit: None,
+        }])
+        .is_err());
+
+        // the default map keeps the DAS names, only giving the units of the AGN-201 channels
+        let defaults = ChannelMap::load(None).unwrap();
+        assert_eq!(
+            defaults.output_headers(&headers),
+            vec!["Ch1 (CPS)", "Ch2 (W)", "Ch2_W", "column_4", "Timestamp"]
+        );
+        assert_eq!(defaults.unit("Ch2_Watts"), Some("W"));
+    }
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
-            }
-        };
+    #[test]
+    fn output_columns_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        // the second row leaves out the closing separator of its empty comment
+        fs::write(
+            &path,
+            "LabVIEW Measurement,\r
+Separator,Comma\r
+Decimal_Separator,.\r
+***End_of_Header***\r
+,\r
+Channels,2,\r
+Samples,1,1,\r
+X0,0.0000000000000000E+0,0.0000000000000000E+0,\r
+Delta_X,1.000000,1.000000,\r
+***End_of_Header***\r
+X_Value,Ch1 (CPS),CCR_cm,Comment,\r
+0.0,1.0,2,rods in,\r
+1.0,1.5,3\r
+",
+        )
+        .unwrap();
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+        let new_output = |_| output_path(&path);
+        let validator = Validator::load(None).unwrap();
+        let channels = ChannelMap::load(None).unwrap();
+        let options = csv_options(&validator, &channels);
+        let outputs = save_pass(&path, initial_process(path.clone(), &new_output, &options, 0, Tz::UTC).unwrap(), &db).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[1].channel, Channel::Manifest);
+
+        // without a configured channel map the columns keep their DAS names
+        let output = fs::read_to_string(part_path(&outputs[0].path)).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            "X_Value,Ch1 (CPS),CCR_cm,Comment,DateTime,Timestamp,RunId"
+        );
+        assert!(lines[1].starts_with("0.0,1.0,2,rods in,2023-02-13T14:29:00"));
+        assert!(lines[2].starts_with("1.0,1.5,3,,2023-02-13T14:29:00"));
+        assert!(lines[2].ends_with(",Feb_13_2023_14_29"));
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/Ch 3 Engineering Data.txt").unwrap(),
-            db.clone(),
-            Some(ts_tx.clone()),
-            Some(g_tx.clone()),
+    #[test]
+    fn event_run_id_column_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        fs::write(&path, events(0, 1)).unwrap();
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let validator = Validator::load(None).unwrap();
+        let channels = ChannelMap::new(&[ChannelMapping {
+            column: String::from("Ch1 (CPS)"),
+            name: Some(String::from("startup_cps")),
+            unit: None,
+        }])
+        .unwrap();
+        let options = csv_options(&validator, &channels);
+
+        // event columns keep their names, the run id is named as it is in engineering outputs
+        let new_output = |_| output_path(&path);
+        let outputs = save_pass(&path, initial_event_process(path.clone(), &new_output, &options, 0, Tz::UTC).unwrap(), &db).unwrap();
+        let output = fs::read_to_string(part_path(&outputs[0].path)).unwrap();
+        assert_eq!(
+            output.lines().next().unwrap(),
+            "Event,Index,DateTime,EventTime,Category,Component,Text,run_id"
         );
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        let mut message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
-            }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
-            }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
-            }
+#[cfg(test)]
+mod aggregate_tests {
+    use crate::aggregate::{aggregate_path, Aggregation, Aggregator};
+    use crate::channels::ChannelMap;
+    use crate::classify::FileKind;
+    use crate::config::Configuration;
+    use crate::format::OutputFormat;
+    use crate::journal::Channel;
+    use crate::tests::tail_tests::{process_channels, run_directory, LVM_HEADER};
+    use crate::{create_tables, fetch_current};
+    use chrono::{Duration, TimeZone, Utc};
+    use rusqlite::Connection;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn windows_test() {
+        let aggregation = Aggregation {
+            interval_seconds: 2.0,
+            raw: false,
         };
+        let start = Utc.with_ymd_and_hms(2023, 2, 13, 14, 29, 0).unwrap();
+        let headers: Vec<String> = ["X_Value", "Ch1_CPS", "CCR_cm", "Comment", "DateTime", "Timestamp"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+
+        let mut aggregator = Aggregator::new(&aggregation, start, None);
+        aggregator.set_columns(&headers);
+        assert_eq!(
+            aggregator.headers(&headers, &ChannelMap::load(None).unwrap()),
+            vec![
+                "DateTime", "Timestamp", "Ch1_CPS_mean", "Ch1_CPS_min", "Ch1_CPS_max",
+                "Ch1_CPS_last", "Ch1_CPS_count", "CCR_cm_mean", "CCR_cm_min", "CCR_cm_max",
+                "CCR_cm_last", "CCR_cm_count",
+            ]
+        );
 
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
+        let row = |values: [&str; 3]| csv::StringRecord::from(vec![values[0], values[1], values[2], ""]);
+        assert!(aggregator.add(&row(["0.0", "1.0", "NaN"]), start).is_none());
+        assert!(aggregator.add(&row(["1.5", "4.0", ""]), start + Duration::milliseconds(1500)).is_none());
+
+        // a sample in the next window closes the first, channels without a value are left empty
+        let closed = aggregator.add(&row(["2.0", "5.0", "2"]), start + Duration::seconds(2)).unwrap();
+        assert_eq!(
+            closed.iter().collect::<Vec<&str>>(),
+            vec![
+                "2023-02-13T14:29:00+00:00", "2023-02-13T14:29:00+00:00", "2.5", "1", "4", "4", "2",
+                "", "", "", "", "0",
+            ]
+        );
+        assert_eq!(aggregator.window().unwrap().index, 1);
 
-        let comparison = File::open(Path::new("./test_data/Ch3EngineeringResults.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+        let closed = aggregator.close().unwrap();
+        assert_eq!(&closed[1], "2023-02-13T14:29:02+00:00");
+        assert_eq!(&closed[7], "2");
+        assert!(aggregator.window().is_none());
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+        assert_eq!(
+            aggregate_path(Path::new("out/output.csv"), &aggregation, OutputFormat::Csv),
+            Path::new("out/output_2s.csv")
+        );
     }
 
-    #[tokio::test]
-    async fn process_digitals_data_test() {
-        fs::remove_file(".test.db").await;
-        let (ts_tx, mut ts_rx) = unbounded_channel();
-        let (g_tx, mut g_rx) = unbounded_channel();
-
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
+    #[test]
+    fn aggregated_output_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let aggregation = Aggregation {
+            interval_seconds: 2.0,
+            raw: false,
         };
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
-        };
+        fs::write(
+            &path,
+            format!("{LVM_HEADER}0.0,1.0,2,\r\n1.0,3.0,2,\r\n2.0,5.0,3,\r\n3.0,7.0,3,\r\n"),
+        )
+        .unwrap();
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
-            }
+        // only the closed window is sent, the open one is kept with the file
+        let outputs = process_channels(&path, &db, false, Some(&aggregation));
+        assert_eq!(outputs.len(), 1);
+        let (channel, rows) = &outputs[0];
+        assert_eq!(*channel, Channel::Timeseries);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][2], "2");
+        assert_eq!(&rows[0][6], "2");
+        assert_eq!(&rows[0][12], "Feb_13_2023_14_29");
+        assert_eq!(fetch_current(&path, &db).unwrap().unwrap().window.unwrap().index, 1);
+
+        // the stored window is carried on into the next pass, and raw rows can be kept as well
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "4.0,9.0,4,\r\n").unwrap();
+        let aggregation = Aggregation {
+            interval_seconds: 2.0,
+            raw: true,
         };
+        let outputs = process_channels(&path, &db, false, Some(&aggregation));
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].1.len(), 1);
+        assert_eq!(&outputs[0].1[0][0], "4.0");
+        let rows = &outputs[1].1;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][1], "2023-02-13T14:29:02+00:00");
+        assert_eq!(&rows[0][2], "6");
+        assert_eq!(&rows[0][6], "2");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+    #[test]
+    fn aggregation_config_test() {
+        let mut config = Configuration::default();
+        config.aggregation.insert(
+            FileKind::Engineering,
+            Aggregation {
+                interval_seconds: 10.0,
+                raw: true,
+            },
+        );
+        assert!(config.validate().is_ok());
 
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/Digitals.txt").unwrap(),
-            db.clone(),
-            Some(ts_tx.clone()),
-            Some(g_tx.clone()),
+        config.aggregation.insert(
+            FileKind::Events,
+            Aggregation {
+                interval_seconds: 10.0,
+                raw: true,
+            },
         );
+        assert!(config.validate().is_err());
+    }
+}
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+#[cfg(test)]
+mod format_tests {
+    use crate::channels::ChannelMap;
+    use crate::classify::FileKind;
+    use crate::config::Configuration;
+    use crate::format::{
+        output_stem, sibling_path, ChunkLimits, ColumnType, OutputFormat, RowWriter,
+    };
+    use crate::journal::{part_path, Channel};
+    use crate::tests::journal_tests::received;
+    use crate::tests::tail_tests::{csv_options, events, read_rows, run_directory, LVM_HEADER};
+    use crate::validate::Validator;
+    use crate::{
+        create_tables, fetch_current, initial_process, save_pass, tail_process, ISUProcessor,
+        OutputOptions,
+    };
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::Tz;
+    use arrow_array::{Array, Float64Array, Int64Array, StringArray, TimestampMicrosecondArray};
+    use arrow_schema::{DataType, TimeUnit};
+    use flate2::read::GzDecoder;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use rusqlite::Connection;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use tokio::sync::mpsc::unbounded_channel;
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        let mut message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
-            }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
-            }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
-            }
-        };
+    fn headers() -> Vec<String> {
+        ["timestamp", "ch1_cps", "index", "comment"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect()
+    }
+
+    const TYPES: [ColumnType; 4] = [
+        ColumnType::Timestamp,
+        ColumnType::Float,
+        ColumnType::Integer,
+        ColumnType::Text,
+    ];
+
+    fn write(path: &Path, format: OutputFormat) {
+        let mut writer = RowWriter::create(path, format, &headers(), &TYPES).unwrap();
+        for row in [
+            ["2023-02-13T14:29:00+00:00", "1.5", "0", "rods in"],
+            ["2023-02-13T14:29:01.500+00:00", "bad", "1", ""],
+        ] {
+            writer.write(&csv::StringRecord::from(row.to_vec())).unwrap();
+        }
+        writer.finish().unwrap();
+    }
 
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
+    #[test]
+    fn output_paths_test() {
+        assert_eq!(output_stem(Path::new("out/output.csv.gz")), "output");
+        assert_eq!(output_stem(Path::new("out/output.parquet")), "output");
+        assert_eq!(
+            sibling_path(Path::new("out/output.csv.gz"), "quarantine", "csv.gz"),
+            Path::new("out/output_quarantine.csv.gz")
+        );
+    }
 
-        let comparison = File::open(Path::new("./test_data/DigitalsResults.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+    #[test]
+    fn csv_gzip_test() {
+        let dir = run_directory();
+        let path = dir.join("output.csv.gz");
+        write(&path, OutputFormat::CsvGzip);
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+        let mut reader = csv::Reader::from_reader(GzDecoder::new(File::open(part_path(&path)).unwrap()));
+        assert_eq!(reader.headers().unwrap(), &csv::StringRecord::from(headers()));
+        let rows: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[1][1], "bad");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
     }
 
-    #[tokio::test]
-    async fn process_reduced_test() {
-        fs::remove_file(".test.db").await;
-        let (ts_tx, mut ts_rx) = unbounded_channel();
-        let (g_tx, mut g_rx) = unbounded_channel();
+    #[test]
+    fn parquet_test() {
+        let dir = run_directory();
+        let path = dir.join("output.parquet");
+        write(&path, OutputFormat::Parquet);
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(part_path(&path)).unwrap()).unwrap();
+        let schema = builder.schema().clone();
+        assert_eq!(
+            schema.field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+        );
+        assert_eq!(schema.field(1).data_type(), &DataType::Float64);
+        assert_eq!(schema.field(2).data_type(), &DataType::Int64);
+        assert_eq!(schema.field(3).data_type(), &DataType::Utf8);
+
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let times = batch.column(0).as_any().downcast_ref::<TimestampMicrosecondArray>().unwrap();
+        assert_eq!(times.value(1) - times.value(0), 1_500_000);
+
+        // values that aren't of the column's type are nulls
+        let readings = batch.column(1).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(readings.value(0), 1.5);
+        assert!(readings.is_null(1));
+        let indexes = batch.column(2).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(indexes.value(1), 1);
+        let comments = batch.column(3).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(comments.value(0), "rods in");
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
+    #[test]
+    fn chunk_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        fs::write(
+            &path,
+            format!("{LVM_HEADER}0.0,1.0,2,\r\n1.0,1.5,2,\r\n2.0,2.0,3,\r\n"),
+        )
+        .unwrap();
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+
+        let (validator, channels) = (Validator::load(None).unwrap(), ChannelMap::load(None).unwrap());
+        let options = OutputOptions {
+            limits: ChunkLimits {
+                rows: Some(2),
+                bytes: None,
+            },
+            ..csv_options(&validator, &channels)
         };
-
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
+        let new_output = |sequence| dir.join(format!("output_{sequence}.csv"));
+        let rows = |outputs: &[PathBuf]| -> Vec<usize> {
+            outputs.iter().map(|o| read_rows(&part_path(o)).len()).collect()
         };
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
-            }
-        };
+        let outputs: Vec<PathBuf> = save_pass(&path, initial_process(path.clone(), &new_output, &options, 0, Tz::UTC).unwrap(), &db)
+            .unwrap()
+            .into_iter()
+            .filter(|o| o.channel == Channel::Timeseries)
+            .map(|o| o.path)
+            .collect();
+        assert_eq!(outputs, vec![dir.join("output_0.csv"), dir.join("output_1.csv")]);
+        assert_eq!(rows(&outputs), vec![2, 1]);
+
+        // the next pass starts a new chunk after the last one
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"3.0,2.5,3,\r\n").unwrap();
+        let current = fetch_current(&path, &db).unwrap().unwrap();
+        assert_eq!(current.sequence, 2);
+        let outputs: Vec<PathBuf> = save_pass(&path, tail_process(current, path.clone(), &new_output, &options, Tz::UTC).unwrap(), &db)
+            .unwrap()
+            .into_iter()
+            .filter(|o| o.channel == Channel::Timeseries)
+            .map(|o| o.path)
+            .collect();
+        assert_eq!(outputs, vec![dir.join("output_2.csv")]);
+        assert_eq!(rows(&outputs), vec![1]);
+        assert_eq!(fetch_current(&path, &db).unwrap().unwrap().sequence, 3);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+    #[test]
+    fn chunk_name_test() {
+        let config = Configuration {
+            output_name: String::from("{source}_{uuid}"),
+            chunk_bytes: Some(1024 * 1024),
+            ..Configuration::default()
+        };
+        config.validate().unwrap();
+        let run_start = Utc.with_ymd_and_hms(2023, 2, 13, 21, 29, 0).unwrap();
+        let source = Path::new("/das/Feb_13_2023_14_29/Events.txt");
 
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/Reduced Raw Data.txt").unwrap(),
-            db.clone(),
-            Some(ts_tx.clone()),
-            Some(g_tx.clone()),
-        );
+        let name = config.output_path(FileKind::Events, run_start, source, 7, "csv");
+        assert!(name.to_str().unwrap().ends_with("_000007.csv"));
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+        let config = Configuration {
+            output_name: String::from("{source}_{sequence}_{uuid}"),
+            ..config
+        };
+        let name = config.output_path(FileKind::Events, run_start, source, 7, "csv");
+        assert!(name.to_str().unwrap().contains("Events_000007_"));
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        let mut message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
-            }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
-            }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
-            }
+        // zero would start a new output for every row
+        let config = Configuration {
+            chunk_rows: Some(0),
+            ..config
         };
+        assert!(config.validate().is_err());
+    }
 
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
+    #[test]
+    fn replaced_file_sequence_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        let processor = ISUProcessor::with_config(Configuration {
+            state_db: PathBuf::from(":memory:"),
+            output_dir: dir.join("output"),
+            output_name: String::from("{source}_{sequence}_{uuid}"),
+            ..Configuration::default()
+        })
+        .unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, mut ts_rx) = unbounded_channel();
+        let name = |output: &PathBuf| output.file_name().unwrap().to_string_lossy().to_string();
+
+        fs::write(&path, events(0, 3)).unwrap();
+        processor.process_file(path.clone(), Some(ts_chan.clone()), None).unwrap();
+        assert!(name(&received(&mut ts_rx)[0]).starts_with("Events_000000_"));
+
+        // the file that replaces it carries on the numbering instead of reusing the first name
+        let replacement = dir.join("Events.new");
+        fs::write(&replacement, events(10, 13)).unwrap();
+        fs::rename(&replacement, &path).unwrap();
+        processor.process_file(path, Some(ts_chan), None).unwrap();
+        assert!(name(&received(&mut ts_rx)[0]).starts_with("Events_000001_"));
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        let comparison = File::open(Path::new("./test_data/ReducedResults.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+#[cfg(test)]
+mod backfill_tests {
+    use crate::backfill::{backfill, BackfillOptions, MEMORY_STATE_DB};
+    use crate::classify::FileKind;
+    use crate::config::Configuration;
+    use crate::tests::tail_tests::{events, run_directory, LVM_HEADER};
+    use chrono::{TimeZone, Utc};
+    use std::fs;
+    use std::path::PathBuf;
+
+    // runs writes two runs into one directory, returning it
+    fn runs() -> PathBuf {
+        let dir = run_directory();
+        let root = dir.parent().unwrap().to_path_buf();
+        fs::write(dir.join("Events.txt"), events(0, 3)).unwrap();
+        fs::write(
+            dir.join("Most Engineering Data.txt"),
+            format!("{LVM_HEADER}0.0,1.0,2,\r\n1.0,1.5,2,\r\n"),
+        )
+        .unwrap();
+        fs::write(dir.join("notes.tmp"), "ignored").unwrap();
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+        let later = root.join("Feb_14_2023_09_00");
+        fs::create_dir_all(&later).unwrap();
+        fs::write(later.join("Events.txt"), events(0, 1)).unwrap();
+        fs::write(root.join("README.txt"), "not a run").unwrap();
+        root
     }
 
-    #[tokio::test]
-    async fn process_temperature_test() {
-        fs::remove_file(".test.db").await;
-        let (ts_tx, mut ts_rx) = unbounded_channel();
-        let (g_tx, mut g_rx) = unbounded_channel();
+    fn config(root: &std::path::Path) -> Configuration {
+        Configuration {
+            state_db: PathBuf::from(MEMORY_STATE_DB),
+            output_dir: root.join("outputs"),
+            ..Configuration::default()
+        }
+    }
 
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
+    #[test]
+    fn backfill_test() {
+        let root = runs();
+        let options = BackfillOptions {
+            paths: vec![root.clone()],
+            graph: true,
+            ..BackfillOptions::default()
         };
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
-        };
+        let files = backfill(config(&root), &options).unwrap();
+        let kinds: Vec<FileKind> = files.iter().map(|f| f.kind).collect();
+        assert_eq!(kinds, vec![FileKind::Events, FileKind::Engineering, FileKind::Events]);
+
+        // every file has its timeseries output, manifest and graph, published and kept. The later run ends the
+        // first and the end of the backfill ends the later one, both summaries come with the last file
+        for file in &files {
+            assert!(file.outputs.iter().all(|o| o.exists() && o.starts_with(root.join("outputs"))));
+        }
+        assert_eq!(files[0].outputs.len(), 3);
+        assert_eq!(files[1].outputs.len(), 3);
+        assert_eq!(files[2].outputs.len(), 5);
+        let summaries = files[2]
+            .outputs
+            .iter()
+            .filter(|o| fs::read_to_string(o).unwrap().contains("run_complete"));
+        assert_eq!(summaries.count(), 2);
+        let rows = fs::read_to_string(&files[1].outputs[0]).unwrap();
+        assert_eq!(rows.lines().count(), 3);
+
+        fs::remove_dir_all(root).unwrap();
+    }
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
-            }
+    #[test]
+    fn backfill_failure_test() {
+        let root = runs();
+        let dir = root.join("Feb_13_2023_14_29");
+        let header = LVM_HEADER.replace("Channels,2,", "Channels,two,");
+        fs::write(dir.join("Most Engineering Data.txt"), format!("{header}0.0,1.0,2,\r\n")).unwrap();
+        let options = BackfillOptions {
+            paths: vec![root.clone()],
+            graph: true,
+            ..BackfillOptions::default()
         };
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+        // the bad file is recorded as failed, the rest of the files and the run summaries are still sent
+        let files = backfill(config(&root), &options).unwrap();
+        let failed: Vec<bool> = files.iter().map(|f| f.error.is_some()).collect();
+        assert_eq!(failed, vec![false, true, false]);
+        assert!(files[1].outputs.is_empty());
+        assert_eq!(files[0].outputs.len(), 3);
+        assert_eq!(files[2].outputs.len(), 5);
 
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/Temperature.txt").unwrap(),
-            db.clone(),
-            Some(ts_tx.clone()),
-            Some(g_tx.clone()),
-        );
+        fs::remove_dir_all(root).unwrap();
+    }
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+    #[test]
+    fn backfill_selection_test() {
+        let root = runs();
+        let options = BackfillOptions {
+            paths: vec![root.clone()],
+            from: Some(Utc.with_ymd_and_hms(2023, 2, 14, 0, 0, 0).unwrap()),
+            dry_run: true,
+            ..BackfillOptions::default()
+        };
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        let mut message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
-            }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
-            }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
-            }
+        // a dry run lists the files of the runs in range and writes nothing
+        let files = backfill(config(&root), &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, root.join("Feb_14_2023_09_00").join("Events.txt"));
+        assert!(files[0].outputs.is_empty());
+        assert!(!root.join("outputs").exists());
+
+        let options = BackfillOptions {
+            to: Some(Utc.with_ymd_and_hms(2023, 2, 14, 0, 0, 0).unwrap()),
+            from: None,
+            ..options
+        };
+        assert_eq!(backfill(config(&root), &options).unwrap().len(), 2);
+
+        // a directory without runs is most likely a mistyped path
+        let options = BackfillOptions {
+            paths: vec![root.join("outputs_missing")],
+            ..options
+        };
+        assert!(backfill(config(&root), &options).is_err());
+        fs::create_dir_all(root.join("empty")).unwrap();
+        let options = BackfillOptions {
+            paths: vec![root.join("empty")],
+            ..options
         };
+        assert!(backfill(config(&root), &options).is_err());
 
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
 
-        let comparison = File::open(Path::new("./test_data/TemperatureResults.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+#[cfg(test)]
+mod admin_tests {
+    use crate::admin::StateAdmin;
+    use crate::config::Configuration;
+    use crate::create_tables;
+    use crate::runs::RunStats;
+    use crate::tests::journal_tests::received;
+    use crate::tests::tail_tests::{events, process, run_directory, LVM_HEADER};
+    use crate::ISUProcessor;
+    use rusqlite::Connection;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use tokio::sync::mpsc::unbounded_channel;
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+    fn append(path: &std::path::Path, rows: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(rows.as_bytes()).unwrap();
     }
 
-    #[tokio::test]
-    async fn process_event_test() {
-        fs::remove_file(".test.db").await;
-        let (ts_tx, mut ts_rx) = unbounded_channel();
-        let (g_tx, mut g_rx) = unbounded_channel();
+    // accepted_rows is how many rows the stats of the run count
+    fn accepted_rows(db: &Connection) -> i64 {
+        let stats: String = db.query_row("SELECT stats FROM isu_run", [], |row| row.get(0)).unwrap();
+        serde_json::from_str::<RunStats>(&stats).unwrap().accepted_rows
+    }
 
-        let options = match SqliteConnectOptions::from_str("sqlite://.test.db") {
-            Ok(o) => o,
-            Err(e) => {
-                panic!("unable to create options for sqlite connection {e:?}")
-            }
+    #[test]
+    fn rewind_test() {
+        let dir = run_directory();
+        let config = Configuration {
+            state_db: dir.join("state.db"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
         };
+        let events_path = dir.join("Events.txt");
+        fs::write(&events_path, events(0, 5)).unwrap();
+        let engineering = dir.join("Most Engineering Data.txt");
+        fs::write(
+            &engineering,
+            format!("{LVM_HEADER}0.0,1.0,2,\r\n1.0,1.5,2,\r\n2.0,2.0,3,\r\n3.0,2.5,3,\r\n"),
+        )
+        .unwrap();
 
-        let db = match SqlitePool::connect_with(options.create_if_missing(true)).await {
-            Ok(d) => d,
-            Err(e) => {
-                panic!("unable to connect to sqlite database {e:?}")
-            }
+        let db = Connection::open(&config.state_db).unwrap();
+        create_tables(&db).unwrap();
+        assert_eq!(process(&events_path, &db, true).len(), 5);
+        assert_eq!(process(&engineering, &db, false).len(), 4);
+
+        let admin = StateAdmin::open(config).unwrap();
+        assert_eq!(admin.files(None).unwrap().len(), 2);
+        assert_eq!(admin.files(Some(&dir)).unwrap().len(), 2);
+        let file = admin.files(Some(&events_path)).unwrap().remove(0);
+        assert_eq!(file.last_index, 5);
+        assert_eq!(file.unprocessed_bytes(), Some(0));
+        assert!(file.updated_at.is_some());
+        append(&events_path, &events(5, 7));
+        let file = admin.files(Some(&events_path)).unwrap().remove(0);
+        assert_eq!(file.unprocessed_bytes(), Some(events(5, 7).len() as u64));
+
+        // rewound files continue from the row they were rewound to, the run counts each row once
+        assert_eq!(accepted_rows(&db), 9);
+        admin.rewind(&events_path, 3).unwrap();
+        assert_eq!(accepted_rows(&db), 7);
+        let rows = process(&events_path, &db, true);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(&rows[0][1], "3");
+        admin.rewind(&engineering, 2).unwrap();
+        let rows = process(&engineering, &db, false);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][0], "2.0");
+        assert_eq!(accepted_rows(&db), 11);
+
+        // rows that haven't been read yet can't be rewound to
+        assert!(admin.rewind(&engineering, 10).is_err());
+        assert!(admin.rewind(&dir.join("Missing.txt"), 0).is_err());
+
+        // rewinding a run to the start forgets its files
+        assert_eq!(admin.rewind(&dir, 0).unwrap().len(), 2);
+        assert!(admin.files(None).unwrap().is_empty());
+        assert_eq!(accepted_rows(&db), 0);
+        assert_eq!(process(&events_path, &db, true).len(), 7);
+        assert_eq!(accepted_rows(&db), 7);
+
+        // the summary of a closed run has been sent, its rows can't be read again
+        db.execute("UPDATE isu_run SET closed_at = '2023-02-14T00:00:00+00:00'", []).unwrap();
+        assert!(admin.rewind(&events_path, 2).is_err());
+        assert_eq!(admin.files(Some(&events_path)).unwrap()[0].last_index, 7);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn finish_test() {
+        let dir = run_directory();
+        let config = Configuration {
+            state_db: dir.join("state.db"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
         };
+        let path = dir.join("Events.txt");
+        fs::write(&path, events(0, 2)).unwrap();
+
+        let processor = ISUProcessor::with_config(config.clone()).unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, mut ts_rx) = unbounded_channel();
+        processor.process_file(path.clone(), Some(ts_chan.clone()), None).unwrap();
+        assert_eq!(received(&mut ts_rx).len(), 1);
+
+        // finished files are skipped until they're rewound
+        let admin = StateAdmin::open(config).unwrap();
+        admin.finish(&path).unwrap();
+        assert!(admin.files(Some(&path)).unwrap()[0].finished);
+        append(&path, &events(2, 4));
+        processor.process_file(path.clone(), Some(ts_chan.clone()), None).unwrap();
+        assert!(received(&mut ts_rx).is_empty());
+
+        admin.rewind(&path, 2).unwrap();
+        processor.process_file(path.clone(), Some(ts_chan), None).unwrap();
+        assert_eq!(received(&mut ts_rx).len(), 1);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        let isu = match ISUProcessor::new() {
-            Ok(p) => p,
-            Err(e) => {
-                panic!("init_test failed {e:?}")
-            }
+#[cfg(test)]
+mod runs_tests {
+    use crate::aggregate::Aggregation;
+    use crate::classify::FileKind;
+    use crate::config::Configuration;
+    use crate::runs::{self, RunStats};
+    use crate::tests::journal_tests::received;
+    use crate::tests::tail_tests::{events, read_rows, run_directory, LVM_HEADER};
+    use crate::timestamps::format_time;
+    use crate::{create_tables, fetch_current, ISUProcessor};
+    use chrono::{Duration, TimeZone, Utc};
+    use chrono_tz::Tz;
+    use jester_core::DataSourceMessage;
+    use rusqlite::Connection;
+    use serde_json::Value;
+    use std::fs;
+    use std::path::PathBuf;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    #[test]
+    fn run_summary_test() {
+        let dir = run_directory();
+        let mut config = Configuration {
+            state_db: PathBuf::from(":memory:"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
         };
+        config.aggregation.insert(
+            FileKind::Engineering,
+            Aggregation {
+                interval_seconds: 2.0,
+                raw: false,
+            },
+        );
+        let processor = ISUProcessor::with_config(config).unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, mut ts_rx) = unbounded_channel();
+        let (graph_chan, mut graph_rx) = unbounded_channel();
+
+        let engineering = dir.join("Most Engineering Data.txt");
+        fs::write(
+            &engineering,
+            format!(
+                "{}0.0,1.0,10,\r\n1.0,3.0,40,\r\n2.0,5.0,20,\r\n3.0,7.0,5,\r\n",
+                LVM_HEADER.replace("CCR_cm", "Ch2_Watts")
+            ),
+        )
+        .unwrap();
+        processor
+            .process_file(engineering.clone(), Some(ts_chan.clone()), Some(graph_chan.clone()))
+            .unwrap();
+        assert_eq!(received(&mut ts_rx).len(), 1);
+
+        // the run is still open, nothing has ended it
+        let graphs = received(&mut graph_rx);
+        assert!(graphs.iter().all(|o| !fs::read_to_string(o).unwrap().contains("run_complete")));
+
+        // a newer run ends it, the window it left open is sent along with its summary
+        let later = dir.parent().unwrap().join("Feb_14_2023_09_00");
+        fs::create_dir_all(&later).unwrap();
+        fs::write(later.join("Events.txt"), events(0, 2)).unwrap();
+        received(&mut ts_rx);
+        processor
+            .process_file(later.join("Events.txt"), Some(ts_chan), Some(graph_chan))
+            .unwrap();
 
-        let result = isu.init(db.clone());
-        assert!(result.is_ok(), "{:?}", result.err());
+        let flushed: Vec<Vec<csv::StringRecord>> = received(&mut ts_rx).iter().map(|o| read_rows(o)).collect();
+        assert!(flushed.iter().any(|rows| rows.len() == 1 && &rows[0][1] == "2023-02-13T14:29:02+00:00"));
+        assert!(fetch_current(&engineering, &processor.state.lock().unwrap()).unwrap().unwrap().window.is_none());
+
+        let summaries: Vec<Value> = received(&mut graph_rx)
+            .iter()
+            .map(|o| serde_json::from_str(&fs::read_to_string(o).unwrap()).unwrap())
+            .filter(|v: &Value| v[0]["properties"]["run_complete"] == Value::Bool(true))
+            .collect();
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0][0];
+        assert_eq!(summary["id"], "run:Feb_13_2023_14_29");
+        let properties = &summary["properties"];
+        assert_eq!(properties["accepted_rows"], 4);
+        assert_eq!(properties["rejected_rows"], 0);
+        assert_eq!(properties["duration_seconds"], 3.0);
+        assert_eq!(properties["peak_power_watts"], 40.0);
+        assert_eq!(properties["peak_power_channel"], "Ch2_Watts");
+        assert_eq!(properties["peak_power_time"], "2023-02-13T14:29:01+00:00");
+        assert_eq!(properties["channels"]["Ch1_CPS"]["min"], 1.0);
+        assert_eq!(properties["channels"]["Ch1_CPS"]["max"], 7.0);
+        assert_eq!(properties["channels"]["Ch1_CPS"]["mean"], 4.0);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        std::fs::copy(
-            "./test_data/Feb_13_2023_14_29/Events.txt",
-            "./test_data/Feb_13_2023_14_29/EventsTail.txt",
-        )
+    #[test]
+    fn run_summary_without_graph_test() {
+        let dir = run_directory();
+        let processor = ISUProcessor::with_config(Configuration {
+            state_db: PathBuf::from(":memory:"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
+        })
         .unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, mut ts_rx) = unbounded_channel();
+
+        fs::write(dir.join("Events.txt"), events(0, 2)).unwrap();
+        processor.process_file(dir.join("Events.txt"), Some(ts_chan.clone()), None).unwrap();
+        received(&mut ts_rx);
+
+        // the run is closed, but without a graph channel its summary isn't written or journaled
+        processor.end_runs(ts_chan, None).unwrap();
+        assert!(received(&mut ts_rx).is_empty());
+        let db = processor.state.lock().unwrap();
+        let closed: bool = db
+            .query_row("SELECT closed_at IS NOT NULL FROM isu_run", [], |row| row.get(0))
+            .unwrap();
+        assert!(closed);
+        let pending: i64 = db
+            .query_row("SELECT COUNT(*) FROM isu_pending_output WHERE channel = 'graph'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(pending, 0);
+        let written = fs::read_dir(dir.join("output"))
+            .unwrap()
+            .any(|e| e.unwrap().file_name().to_string_lossy().contains("summary"));
+        assert!(!written);
 
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/EventsTail.txt").unwrap(),
-            db.clone(),
-            Some(ts_tx.clone()),
-            Some(g_tx.clone()),
-        );
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+    #[test]
+    fn idle_timeout_test() {
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let directory = run_directory();
+        let start = Utc.with_ymd_and_hms(2023, 2, 13, 14, 29, 0).unwrap();
+        let stats = RunStats {
+            accepted_rows: 3,
+            ..RunStats::default()
+        };
+        runs::record(&db, &directory, start, &stats, start).unwrap();
+
+        // passes that read nothing don't keep a run open
+        let timeout = Some(Duration::hours(1));
+        runs::record(&db, &directory, start, &RunStats::default(), start + Duration::minutes(50)).unwrap();
+        assert!(runs::ended(&db, start + Duration::minutes(30), timeout, Tz::UTC).unwrap().is_empty());
+        assert!(runs::ended(&db, start + Duration::hours(2), None, Tz::UTC).unwrap().is_empty());
+        let ended = runs::ended(&db, start + Duration::minutes(61), timeout, Tz::UTC).unwrap();
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].stats.accepted_rows, 3);
+
+        // a closed run isn't opened again or closed twice
+        runs::close(&db, &ended[0], start + Duration::minutes(61)).unwrap();
+        runs::record(&db, &directory, start, &stats, start + Duration::hours(3)).unwrap();
+        assert!(runs::ended(&db, start + Duration::hours(5), timeout, Tz::UTC).unwrap().is_empty());
+
+        fs::remove_dir_all(directory.parent().unwrap()).unwrap();
+    }
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        let mut message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
-            }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
-            }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
-            }
+    // summaries reads and removes the graph outputs sent, as Jester would once uploaded, returning
+    // the run summaries
+    fn summaries(rx: &mut UnboundedReceiver<DataSourceMessage>) -> Vec<String> {
+        let outputs = received(rx).into_iter().map(|o| {
+            let records = fs::read_to_string(&o).unwrap();
+            fs::remove_file(o).unwrap();
+            records
+        });
+        outputs.filter(|r| r.contains("run_complete")).collect()
+    }
+
+    #[test]
+    fn idle_run_test() {
+        let dir = run_directory();
+        let config = Configuration {
+            state_db: dir.join("state.db"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
+        };
+        let processor = ISUProcessor::with_config(config.clone()).unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, mut ts_rx) = unbounded_channel();
+        let (graph_chan, mut graph_rx) = unbounded_channel();
+        let idle = |processor: &ISUProcessor| {
+            let last_activity = format_time(Some(Utc::now() - Duration::hours(2)));
+            processor.state.lock().unwrap().execute("UPDATE isu_run SET last_activity = ?1", [last_activity]).unwrap();
         };
 
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
+        // the DAS stops writing and no other file arrives, the run is closed between process calls
+        fs::write(dir.join("Events.txt"), events(0, 2)).unwrap();
+        processor
+            .process_file(dir.join("Events.txt"), Some(ts_chan.clone()), Some(graph_chan.clone()))
+            .unwrap();
+        received(&mut ts_rx);
+        assert!(summaries(&mut graph_rx).is_empty());
+        processor.close_idle_runs().unwrap();
+        assert!(summaries(&mut graph_rx).is_empty());
+        idle(&processor);
+        processor.close_idle_runs().unwrap();
+        assert_eq!(summaries(&mut graph_rx).len(), 1);
+
+        // a run that went idle while the plugin was stopped is closed on init and its summary sent
+        // with the first process call
+        let later = dir.parent().unwrap().join("Feb_14_2023_09_00");
+        fs::create_dir_all(&later).unwrap();
+        fs::write(later.join("Events.txt"), events(0, 2)).unwrap();
+        processor
+            .process_file(later.join("Events.txt"), Some(ts_chan.clone()), Some(graph_chan.clone()))
+            .unwrap();
+        assert!(summaries(&mut graph_rx).is_empty());
+        idle(&processor);
+        drop(processor);
+
+        let processor = ISUProcessor::with_config(config).unwrap();
+        processor.close_idle_runs().unwrap();
+        let (ts_chan, _ts_rx) = unbounded_channel();
+        let (graph_chan, mut graph_rx) = unbounded_channel();
+        processor
+            .process_file(later.join("Events.txt"), Some(ts_chan), Some(graph_chan))
+            .unwrap();
+        let sent = summaries(&mut graph_rx);
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].contains("run:Feb_14_2023_09_00"));
 
-        let comparison = File::open(Path::new("./test_data/EventsResults.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+#[cfg(test)]
+mod manifest_tests {
+    use crate::channels::ChannelMap;
+    use crate::journal::{part_path, Channel};
+    use crate::config::Configuration;
+    use crate::format::ChunkLimits;
+    use crate::manifest::{self, Manifest, Parser, PLUGIN_VERSION};
+    use crate::tests::journal_tests::received;
+    use crate::tests::tail_tests::{csv_options, events, process_with, run_directory, LVM_HEADER, SEGMENT_HEADER};
+    use crate::validate::Validator;
+    use crate::{create_tables, ISUProcessor, OutputOptions};
+    use rusqlite::Connection;
+    use serde_json::Value;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use tokio::sync::mpsc::unbounded_channel;
 
-        // now we're testing the tailing
-        let mut file = OpenOptions::new()
-            .append(true)
-            .open("./test_data/Feb_13_2023_14_29/EventsTail.txt")
-            .unwrap();
+    // read_manifest reads a manifest from the properties of the graph record it was written as
+    pub fn read_manifest(path: &Path) -> Manifest {
+        let records: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        serde_json::from_value(records[0]["properties"].clone()).unwrap()
+    }
 
-        let written = file.write(String::from("Test Event\n").as_bytes()).unwrap();
-        assert!(written > 0);
+    fn manifests(outputs: &[crate::journal::Output]) -> Vec<Manifest> {
+        outputs
+            .iter()
+            .filter(|o| o.channel == Channel::Manifest)
+            .map(|o| read_manifest(&part_path(&o.path)))
+            .collect()
+    }
 
-        file.flush().unwrap();
-        let result = isu.process(
-            PathBuf::from_str("./test_data/Feb_13_2023_14_29/EventsTail.txt").unwrap(),
-            db,
-            Some(ts_tx),
-            Some(g_tx),
-        );
+    #[test]
+    fn manifest_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let (validator, channels) = (Validator::load(None).unwrap(), ChannelMap::load(None).unwrap());
+        let options = csv_options(&validator, &channels);
+
+        let data = format!("{LVM_HEADER}0.0,1.0,2,\r\n1.0,1.5,2,\r\n");
+        fs::write(&path, &data).unwrap();
+        let outputs = process_with(&path, &db, false, &options);
+        let first = manifests(&outputs).remove(0);
+        let output = &outputs[0].path;
+        assert_eq!(manifest::manifest_path(output), outputs[1].path);
+        assert_eq!(first.output, output.file_name().unwrap().to_string_lossy());
+        assert_eq!(first.source, path);
+        assert_eq!(first.run_id, "Feb_13_2023_14_29");
+        assert_eq!(first.parser, Parser::Lvm);
+        assert_eq!(first.byte_range, (0, data.len() as u64));
+        assert_eq!((first.first_index, first.last_index, first.rows), (Some(0), Some(1), 2));
+        assert_eq!(first.sha256, manifest::file_hash(&part_path(output)).unwrap());
+        assert_eq!(first.plugin_version, PLUGIN_VERSION);
+
+        // the next pass covers the bytes and rows after the last one, under the same schema
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"2.0,2.0,3,\r\n").unwrap();
+        let next = manifests(&process_with(&path, &db, false, &options)).remove(0);
+        assert_eq!(next.byte_range, (data.len() as u64, data.len() as u64 + 12));
+        assert_eq!((next.first_index, next.last_index, next.rows), (Some(2), Some(2), 1));
+        assert_eq!(next.schema_hash, first.schema_hash);
+        assert_ne!(next.sha256, first.sha256);
+
+        // event outputs have a schema of their own
+        let events_path = dir.join("Events.txt");
+        fs::write(&events_path, events(0, 3)).unwrap();
+        let event = manifests(&process_with(&events_path, &db, true, &options)).remove(0);
+        assert_eq!(event.parser, Parser::Events);
+        assert_eq!((event.first_index, event.last_index, event.rows), (Some(0), Some(2), 3));
+        assert_ne!(event.schema_hash, first.schema_hash);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
 
-        assert!(result.is_ok(), "Error during process call: {result:?}");
+    #[test]
+    fn chunk_range_test() {
+        let dir = run_directory();
+        let path = dir.join("Most Engineering Data.txt");
+        let db = Connection::open_in_memory().unwrap();
+        create_tables(&db).unwrap();
+        let (validator, channels) = (Validator::load(None).unwrap(), ChannelMap::load(None).unwrap());
+        let options = OutputOptions {
+            limits: ChunkLimits {
+                rows: Some(2),
+                bytes: None,
+            },
+            ..csv_options(&validator, &channels)
+        };
+
+        // a new segment starts an output of its own as well as the chunk limit
+        let data = format!("{LVM_HEADER}0.0,1.0,2,\r\n1.0,1.5,2,\r\n2.0,2.0,3,\r\n{SEGMENT_HEADER}3.0,3.5,5,6,\r\n");
+        fs::write(&path, &data).unwrap();
+        let mut ranges: Vec<(u64, u64)> = manifests(&process_with(&path, &db, false, &options))
+            .iter()
+            .map(|m| m.byte_range)
+            .collect();
+        assert_eq!(ranges.len(), 3);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"4.0,4.5,5,6,\r\n5.0,5.5,5,6,\r\n6.0,6.5,5,6,\r\n").unwrap();
+        let next: Vec<(u64, u64)> = manifests(&process_with(&path, &db, false, &options))
+            .iter()
+            .map(|m| m.byte_range)
+            .collect();
+        assert_eq!(next.len(), 2);
+        ranges.extend(next);
+
+        // each chunk starts where the one before it was cut, from the start of the file to its end
+        assert_eq!(ranges[0].0, 0);
+        for pair in ranges.windows(2) {
+            assert!(pair[0].0 < pair[0].1);
+            assert_eq!(pair[0].1, pair[1].0);
+        }
+        assert_eq!(ranges.last().unwrap().1, fs::metadata(&path).unwrap().len());
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn manifest_channel_test() {
+        let dir = run_directory();
+        let path = dir.join("Events.txt");
+        let processor = ISUProcessor::with_config(Configuration {
+            state_db: PathBuf::from(":memory:"),
+            output_dir: dir.join("output"),
+            ..Configuration::default()
+        })
+        .unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, mut ts_rx) = unbounded_channel();
+        let (graph_chan, mut graph_rx) = unbounded_channel();
+
+        // only data goes to the timeseries channel, the manifest updates the output's graph node
+        fs::write(&path, events(0, 3)).unwrap();
+        processor
+            .process_file(path.clone(), Some(ts_chan.clone()), Some(graph_chan))
+            .unwrap();
+        let data = received(&mut ts_rx);
+        assert_eq!(data.len(), 1);
+        let output_name = data[0].file_name().unwrap().to_string_lossy().to_string();
+        let graphs = received(&mut graph_rx);
+        let manifest = manifest::manifest_path(&data[0]);
+        assert!(graphs.contains(&manifest));
+        let records: Value = serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(records[0]["id"], format!("timeseries:{output_name}"));
+        assert_eq!(read_manifest(&manifest).output, output_name);
+
+        // without a graph channel the manifest isn't written or journaled at all
+        fs::write(&path, events(0, 4)).unwrap();
+        processor.process_file(path, Some(ts_chan), None).unwrap();
+        let data = received(&mut ts_rx);
+        assert_eq!(data.len(), 1);
+        let manifest = manifest::manifest_path(&data[0]);
+        assert!(!manifest.exists() && !part_path(&manifest).exists());
+        let journaled: bool = processor
+            .state
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM isu_pending_output WHERE path = ?1)",
+                [manifest.to_string_lossy().as_ref()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!journaled);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
 
-        // we should see a total of one message on the timeseries channel, should be a file
-        message = ts_rx.recv().await.unwrap();
-        let generated = match message {
-            DataSourceMessage::File(f) => {
-                assert!(f.to_str().is_some());
-                f
+#[cfg(test)]
+mod state_tests {
+    use crate::config::Configuration;
+    use crate::create_tables;
+    use crate::manifest::Manifest;
+    use crate::tests::journal_tests::received;
+    use crate::tests::manifest_tests::read_manifest;
+    use crate::tests::tail_tests::{events, run_directory, LVM_HEADER};
+    use crate::ISUProcessor;
+    use std::collections::HashMap;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::thread;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    const RUNS: [&str; 4] = ["Feb_13_2023_14_29", "Feb_13_2023_15_29", "Feb_13_2023_16_29", "Feb_13_2023_17_29"];
+    const ROUNDS: usize = 20;
+
+    #[test]
+    fn concurrent_process_test() {
+        let root = run_directory().parent().unwrap().to_path_buf();
+        let processor = ISUProcessor::with_config(Configuration {
+            state_db: root.join("state.db"),
+            output_dir: root.join("output"),
+            ..Configuration::default()
+        })
+        .unwrap();
+        create_tables(&processor.state.lock().unwrap()).unwrap();
+        let (ts_chan, _ts_rx) = unbounded_channel();
+        let (graph_chan, mut graph_rx) = unbounded_channel();
+
+        let mut files = vec![];
+        for run in RUNS {
+            let dir = root.join(run);
+            fs::create_dir_all(&dir).unwrap();
+            let engineering = dir.join("Most Engineering Data.txt");
+            fs::write(&engineering, LVM_HEADER).unwrap();
+            fs::write(dir.join("Events.txt"), "").unwrap();
+            files.push((engineering, false));
+            files.push((dir.join("Events.txt"), true));
+        }
+
+        // every file is written to by one thread and read by two at once, while the other files are
+        let row = |events_file: bool, i: usize| match events_file {
+            true => events(i, i + 1),
+            false => format!("{i}.0,1.0,2,\r\n"),
+        };
+        let processor = &processor;
+        thread::scope(|scope| {
+            for (path, events_file) in &files {
+                let (chan, graph) = (ts_chan.clone(), graph_chan.clone());
+                scope.spawn(move || {
+                    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+                    for i in 0..ROUNDS {
+                        file.write_all(row(*events_file, i).as_bytes()).unwrap();
+                        processor.process_file(path.clone(), Some(chan.clone()), Some(graph.clone())).unwrap();
+                    }
+                });
+
+                let (chan, graph) = (ts_chan.clone(), graph_chan.clone());
+                scope.spawn(move || {
+                    for _ in 0..ROUNDS {
+                        processor.process_file(path.clone(), Some(chan.clone()), Some(graph.clone())).unwrap();
+                    }
+                });
             }
-            DataSourceMessage::Data(_) => {
-                panic!("wrong message type received")
+        });
+        for (path, _) in &files {
+            processor.process_file(path.clone(), Some(ts_chan.clone()), Some(graph_chan.clone())).unwrap();
+        }
+
+        // the passes over each file read every byte and row once, in order
+        let mut manifests: HashMap<PathBuf, Vec<Manifest>> = HashMap::new();
+        for output in received(&mut graph_rx) {
+            if output.to_string_lossy().ends_with("_manifest.json") {
+                let manifest = read_manifest(&output);
+                manifests.entry(manifest.source.clone()).or_default().push(manifest);
             }
-            DataSourceMessage::Close => {
-                panic!("wrong message type received")
+        }
+        for (path, _) in &files {
+            let mut passes = manifests.remove(path).unwrap();
+            passes.sort_by_key(|m| m.byte_range);
+            let mut end = passes[0].byte_range.0;
+            let mut next_index = 0;
+            for pass in &passes {
+                assert_eq!(pass.byte_range.0, end);
+                end = pass.byte_range.1;
+                if let Some(first) = pass.first_index {
+                    assert_eq!(first, next_index);
+                    next_index = pass.last_index.unwrap() + 1;
+                }
             }
-        };
-
-        let generated_file = File::open(&generated).unwrap();
-        let generated_checksum = adler32(BufReader::new(generated_file)).unwrap();
-
-        let comparison = File::open(Path::new("./test_data/EventsTailResults.csv")).unwrap();
-        let comparison_checksum = adler32(BufReader::new(comparison)).unwrap();
+            assert_eq!(end, fs::metadata(path).unwrap().len());
+            assert_eq!(next_index, ROUNDS as i64);
+            assert_eq!(passes.iter().map(|p| p.rows).sum::<u64>(), ROUNDS as u64);
+        }
+
+        // every run but the last has been ended by a later one
+        let closed: i64 = processor
+            .state
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM isu_run WHERE closed_at IS NOT NULL", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(closed, RUNS.len() as i64 - 1);
 
-        assert_eq!(generated_checksum, comparison_checksum);
-        fs::remove_file(generated).await.unwrap();
+        fs::remove_dir_all(root).unwrap();
     }
 }
@@ -0,0 +1,107 @@
+use crate::errors::ISUProcessorError;
+use crate::lvm::LvmHeader;
+use chrono::{
+    DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+
+// the DAS names each run folder after the local time the run was started
+pub const RUN_DIRECTORY_FORMAT: &str = "%b_%d_%Y_%H_%M";
+// format of run starts stored before the plugin was timezone aware
+pub const STORED_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+// the engineering data column holding each sample's time, and the columns the plugin adds to rows
+pub const X_VALUE_COLUMN: &str = "X_Value";
+pub const DATETIME_COLUMN: &str = "DateTime";
+pub const TIMESTAMP_COLUMN: &str = "Timestamp";
+
+// LabVIEW absolute times are seconds since 1904-01-01 00:00:00 UTC. Anything past the unix epoch in
+// that scale can't be a relative offset from the start of a run
+const LABVIEW_UNIX_EPOCH_SECONDS: f64 = 2_082_844_800.0;
+
+// das_timezone is the configured timezone of the DAS clock, UTC if none is set
+pub fn das_timezone(tz: Option<&str>) -> Result<Tz, ISUProcessorError> {
+    match tz {
+        None => Ok(Tz::UTC),
+        Some(tz) => parse_timezone(tz),
+    }
+}
+
+pub fn parse_timezone(tz: &str) -> Result<Tz, ISUProcessorError> {
+    tz.parse::<Tz>()
+        .map_err(|_| ISUProcessorError::TimezoneError(format!("unknown timezone {tz}")))
+}
+
+// to_utc converts a DAS wall clock time to UTC. Around DST transitions a wall clock time can occur
+// twice or not at all, in both cases we pick the instant the DAS clock most likely meant
+pub fn to_utc(time: NaiveDateTime, tz: Tz) -> Result<DateTime<Utc>, ISUProcessorError> {
+    match tz.from_local_datetime(&time) {
+        LocalResult::Single(t) => Ok(t.with_timezone(&Utc)),
+        // clocks fell back and the time happened twice, the run started the first time it was seen
+        LocalResult::Ambiguous(earliest, _) => Ok(earliest.with_timezone(&Utc)),
+        // clocks sprang forward over this time, so it is an hour later than the wall clock says
+        LocalResult::None => match tz.from_local_datetime(&(time + Duration::hours(1))) {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => Ok(t.with_timezone(&Utc)),
+            LocalResult::None => Err(ISUProcessorError::TimezoneError(format!(
+                "{time} does not exist in {tz}"
+            ))),
+        },
+    }
+}
+
+pub fn run_start(run_directory: &str, tz: Tz) -> Result<DateTime<Utc>, ISUProcessorError> {
+    to_utc(
+        NaiveDateTime::parse_from_str(run_directory, RUN_DIRECTORY_FORMAT)?,
+        tz,
+    )
+}
+
+// parse_stored_time reads a run start back from the isu table. Rows written before the plugin was
+// timezone aware hold the naive DAS time, which we interpret in the DAS timezone
+pub fn parse_stored_time(time: &str, tz: Tz) -> Result<DateTime<Utc>, ISUProcessorError> {
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(t) => Ok(t.with_timezone(&Utc)),
+        Err(_) => to_utc(NaiveDateTime::parse_from_str(time, STORED_TIME_FORMAT)?, tz),
+    }
+}
+
+// sample_time computes the absolute time of a single row. The row's own X value is preferred, when
+// the DAS leaves it empty we fall back to X0 + index * Delta_X from the LVM segment header
+pub fn sample_time(
+    run_start: DateTime<Utc>,
+    x_value: Option<&str>,
+    header: Option<&LvmHeader>,
+    index: i64,
+) -> Option<DateTime<Utc>> {
+    let x = match x_value.map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        Some(x) => x.parse::<f64>().ok()?,
+        None => {
+            let segment = &header?.segment;
+            segment.x0.first().copied().unwrap_or(0.0)
+                + segment.delta_x.first().copied()? * index as f64
+        }
+    };
+
+    if !x.is_finite() {
+        return None;
+    }
+
+    if x >= LABVIEW_UNIX_EPOCH_SECONDS {
+        let epoch =
+            Utc.from_utc_datetime(&NaiveDate::from_ymd_opt(1904, 1, 1)?.and_hms_opt(0, 0, 0)?);
+        return epoch.checked_add_signed(seconds(x));
+    }
+
+    run_start.checked_add_signed(seconds(x))
+}
+
+fn seconds(x: f64) -> Duration {
+    Duration::microseconds((x * 1_000_000.0).round() as i64)
+}
+
+// all emitted times are RFC 3339 in UTC so that downstream comparisons against NOW() line up
+pub fn format_time(time: Option<DateTime<Utc>>) -> String {
+    match time {
+        None => String::new(),
+        Some(t) => t.to_rfc3339_opts(SecondsFormat::AutoSi, false),
+    }
+}
@@ -0,0 +1,149 @@
+use crate::errors::ISUProcessorError;
+use crate::format::{self, OutputFormat};
+use csv::StringRecord;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+// the column added to quarantined rows saying why they were rejected
+pub const REASON_COLUMN: &str = "reason";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    Number,
+    Integer,
+    Text,
+}
+
+// ChannelRule applies to every column whose name matches the column glob. A value that's empty is
+// only rejected if the rule requires it, the other checks apply to values that are present
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChannelRule {
+    pub column: String,
+    pub value_type: Option<ValueType>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+// rules for the AGN-201 DAS engineering data, every channel is numeric and count rates can't be
+// negative. Comment is free text
+pub const DEFAULT_VALIDATION: &str = r#"[
+    {"column": "X_Value", "value_type": "number", "required": true},
+    {"column": "*CPS*", "value_type": "number", "min": 0},
+    {"column": "*_Watts", "value_type": "number"},
+    {"column": "*_cm", "value_type": "number"},
+    {"column": "Temp", "value_type": "number"}
+]"#;
+
+// quarantine_path is where rows rejected from an output are written, next to the output
+pub fn quarantine_path(output: &Path, format: OutputFormat) -> PathBuf {
+    format::sibling_path(output, "quarantine", format.extension())
+}
+
+pub struct Validator {
+    rules: Vec<(glob::Pattern, ChannelRule)>,
+}
+
+impl Validator {
+    pub fn new(rules: &[ChannelRule]) -> Result<Validator, ISUProcessorError> {
+        let rules = rules
+            .iter()
+            .map(|r| {
+                let pattern = glob::Pattern::new(r.column.as_str()).map_err(|e| {
+                    ISUProcessorError::RuleError(format!("invalid column glob {}: {e}", r.column))
+                })?;
+                Ok((pattern, r.clone()))
+            })
+            .collect::<Result<Vec<(glob::Pattern, ChannelRule)>, ISUProcessorError>>()?;
+
+        Ok(Validator { rules })
+    }
+
+    // load reads the rules from a json file, when no file is configured DEFAULT_VALIDATION is used
+    pub fn load(path: Option<&Path>) -> Result<Validator, ISUProcessorError> {
+        match path {
+            None => Validator::new(&serde_json::from_str::<Vec<ChannelRule>>(
+                DEFAULT_VALIDATION,
+            )?),
+            Some(path) => {
+                let reader = BufReader::new(File::open(path)?);
+                Validator::new(&serde_json::from_reader::<_, Vec<ChannelRule>>(reader)?)
+            }
+        }
+    }
+
+    // columns matches the rules against a set of headers once, so rows are checked without globbing
+    pub fn columns(&self, headers: &[String]) -> ColumnRules {
+        let columns = headers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, h)| {
+                self.rules
+                    .iter()
+                    .filter(|(p, _)| p.matches(h))
+                    .map(move |(_, r)| (i, h.clone(), r.clone()))
+            })
+            .collect();
+
+        ColumnRules { columns }
+    }
+}
+
+pub struct ColumnRules {
+    columns: Vec<(usize, String, ChannelRule)>,
+}
+
+impl ColumnRules {
+    // check returns why the record is invalid, or None if it passes every rule
+    pub fn check(&self, record: &StringRecord) -> Option<String> {
+        let reasons: Vec<String> = self
+            .columns
+            .iter()
+            .filter_map(|(i, name, rule)| {
+                check_value(name, record.get(*i).unwrap_or("").trim(), rule)
+            })
+            .collect();
+
+        if reasons.is_empty() {
+            None
+        } else {
+            Some(reasons.join("; "))
+        }
+    }
+}
+
+fn check_value(name: &str, value: &str, rule: &ChannelRule) -> Option<String> {
+    if value.is_empty() {
+        return rule.required.then(|| format!("{name} is required"));
+    }
+
+    // a min or max makes the column numeric even without a value type
+    let numeric = rule.min.is_some() || rule.max.is_some();
+    let number = match rule.value_type {
+        Some(ValueType::Text) => return None,
+        None if !numeric => return None,
+        Some(ValueType::Integer) => match value.parse::<i64>() {
+            Ok(v) => v as f64,
+            Err(_) => return Some(format!("{name} value {value} is not an integer")),
+        },
+        // NaN and infinity parse as floats but aren't readings
+        _ => match value.parse::<f64>() {
+            Ok(v) if v.is_finite() => v,
+            _ => return Some(format!("{name} value {value} is not a number")),
+        },
+    };
+
+    if let Some(min) = rule.min.filter(|min| number < *min) {
+        return Some(format!("{name} value {value} is below {min}"));
+    }
+
+    if let Some(max) = rule.max.filter(|max| number > *max) {
+        return Some(format!("{name} value {value} is above {max}"));
+    }
+
+    None
+}